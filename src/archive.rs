@@ -0,0 +1,56 @@
+use std::collections::HashSet;
+use std::fs;
+use std::io;
+use std::path::Path;
+
+// Loads the `<extractor> <id>` keys of a yt-dlp download archive.
+pub fn load(archive_file: &Path) -> io::Result<HashSet<String>> {
+    match fs::read_to_string(archive_file) {
+        Ok(contents) => Ok(contents
+            .lines()
+            .map(str::trim)
+            .filter(|line| !line.is_empty())
+            .map(str::to_string)
+            .collect()),
+        Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(HashSet::new()),
+        Err(err) => Err(err),
+    }
+}
+
+// Resolves the archive key of a URL without asking yt-dlp, for the URL forms where the video
+// ID is part of the URL itself. Playlists, channels and other sites return None.
+pub fn canonical_id(url: &str) -> Option<String> {
+    youtube_id(url).map(|id| format!("youtube {}", id))
+}
+
+fn youtube_id(url: &str) -> Option<String> {
+    let rest = url
+        .trim()
+        .trim_start_matches("https://")
+        .trim_start_matches("http://");
+    let (host, path) = rest.split_once('/').unwrap_or((rest, ""));
+    let host = host.trim_start_matches("www.").trim_start_matches("m.");
+
+    let candidate = match host {
+        "youtu.be" => path.split(['?', '#', '/']).next(),
+        "youtube.com" | "music.youtube.com" | "youtube-nocookie.com" => {
+            let (route, query) = path.split_once('?').unwrap_or((path, ""));
+            match route.split('/').next() {
+                Some("watch") => query
+                    .split(['&', '#'])
+                    .find_map(|pair| pair.strip_prefix("v=")),
+                Some("shorts") | Some("embed") | Some("live") | Some("v") => {
+                    route.split('/').nth(1).and_then(|id| id.split('#').next())
+                }
+                _ => None,
+            }
+        }
+        _ => None,
+    }?;
+
+    let valid = candidate.len() == 11
+        && candidate
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_');
+    valid.then(|| candidate.to_string())
+}
@@ -0,0 +1,132 @@
+use std::collections::{BTreeMap, HashMap};
+
+use crate::history;
+use crate::urls;
+
+// aria2c's limit for connections per server (-x).
+pub const MAX_CONNECTIONS: u32 = 16;
+// Connections for a host nothing was downloaded from yet
+const DEFAULT_CONNECTIONS: u32 = 4;
+// Downloads per host the tuning goes by, so it follows hosts that change
+const RECENT: usize = 20;
+// Bounds for the piece size each connection fetches at a time (-k), in MiB
+const MIN_SPLIT_MIB: u64 = 1;
+const MAX_SPLIT_MIB: u64 = 64;
+
+// How aria2c downloads from a host.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Settings {
+    pub connections: u32,
+    // MiB
+    pub split_size: u64,
+}
+
+impl Settings {
+    // For yt-dlp's `--downloader-args`; with `resume`, `-c` continues from a file an
+    // interrupted run left.
+    pub fn downloader_args(self, resume: bool) -> String {
+        format!(
+            "aria2c:{}-x {1} -s {1} -k {2}M",
+            if resume { "-c " } else { "" },
+            self.connections,
+            self.split_size
+        )
+    }
+}
+
+struct Sample {
+    connections: u32,
+    size: u64,
+    // Bytes per second
+    speed: f64,
+}
+
+// Speeds the recent aria2c downloads reached per host and connection count, to pick the
+// connections for the next download from the same host.
+#[derive(Default)]
+pub struct Tuning {
+    hosts: HashMap<String, Vec<Sample>>,
+}
+
+impl Tuning {
+    pub fn new(history: &[history::Entry]) -> Tuning {
+        let mut tuning = Tuning::default();
+        for entry in history {
+            tuning.record(entry);
+        }
+        tuning
+    }
+
+    pub fn record(&mut self, entry: &history::Entry) {
+        let Some(connections) = entry.connections else {
+            return;
+        };
+        if entry.seconds <= 0.0 || entry.size == 0 {
+            return;
+        }
+        let samples = self
+            .hosts
+            .entry(urls::domain(&entry.url).to_string())
+            .or_default();
+        samples.push(Sample {
+            connections,
+            size: entry.size,
+            speed: entry.size as f64 / entry.seconds,
+        });
+        if samples.len() > RECENT {
+            samples.remove(0);
+        }
+    }
+
+    // Climbs towards the connection count that was fastest for the host: while the highest
+    // count tried so far is also the fastest, the next download tries twice as many, and while
+    // the lowest one is, half as many, within `min..=max`. Pieces are sized so each connection
+    // gets several of a typical file from the host.
+    pub fn settings(&self, url: &str, (min, max): (u32, u32)) -> Settings {
+        let samples: Vec<&Sample> = self
+            .hosts
+            .get(urls::domain(url))
+            .into_iter()
+            .flatten()
+            .filter(|sample| (min..=max).contains(&sample.connections))
+            .collect();
+
+        let mut speeds: BTreeMap<u32, (f64, u32)> = BTreeMap::new();
+        for sample in &samples {
+            let (total, count) = speeds.entry(sample.connections).or_default();
+            *total += sample.speed;
+            *count += 1;
+        }
+        let best = speeds
+            .iter()
+            .map(|(connections, (total, count))| (*connections, total / f64::from(*count)))
+            .max_by(|a, b| a.1.total_cmp(&b.1))
+            .map(|(connections, _)| connections);
+        let lowest = speeds.keys().next().copied();
+        let highest = speeds.keys().next_back().copied();
+        let connections = match best {
+            None => DEFAULT_CONNECTIONS.clamp(min, max),
+            Some(best) if Some(best) == highest && best < max => (best * 2).min(max),
+            Some(best) if Some(best) == lowest && best > min && lowest != highest => {
+                (best / 2).max(min)
+            }
+            Some(best) => best,
+        };
+
+        let mut sizes: Vec<u64> = samples.iter().map(|sample| sample.size).collect();
+        sizes.sort_unstable();
+        let split_size = match sizes.get(sizes.len() / 2) {
+            Some(size) => {
+                let mib = size / (1 << 20) / (u64::from(connections) * 4);
+                // aria2c wants whole MiB; powers of two keep the pieces even
+                let mib = if mib == 0 { 0 } else { 1 << mib.ilog2() };
+                mib.clamp(MIN_SPLIT_MIB, MAX_SPLIT_MIB)
+            }
+            None => MIN_SPLIT_MIB,
+        };
+        Settings {
+            connections,
+            split_size,
+        }
+    }
+}
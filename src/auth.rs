@@ -0,0 +1,50 @@
+use std::fs;
+use std::io;
+use std::path::Path;
+
+use crate::ytdlp;
+
+// Any public video works; the plugin asks for the device code before extracting it.
+const LOGIN_PROBE_URL: &str = "https://www.youtube.com/watch?v=jNQXAC9IVRw";
+
+// Arguments that make yt-dlp's YouTube OAuth2 plugin authenticate with the tokens cached in
+// `cache_dir` (https://github.com/coletdjnz/yt-dlp-youtube-oauth2).
+pub fn oauth_args(cache_dir: &Path) -> Vec<String> {
+    vec![
+        "--username".to_string(),
+        "oauth2".to_string(),
+        "--password".to_string(),
+        String::new(),
+        "--cache-dir".to_string(),
+        cache_dir.to_string_lossy().into_owned(),
+    ]
+}
+
+// Runs the device-code flow once. yt-dlp prints the code and URL to visit, waits for the
+// login to complete and stores the refresh token in `cache_dir` for later runs.
+pub fn login(cache_dir: &Path) -> io::Result<bool> {
+    fs::create_dir_all(cache_dir)?;
+    println!(
+        "Starting the YouTube device-code login. Follow the instructions yt-dlp prints below."
+    );
+    println!("This needs the yt-dlp YouTube OAuth2 plugin: python3 -m pip install -U yt-dlp-youtube-oauth2");
+
+    let status = ytdlp::command()
+        .args(oauth_args(cache_dir))
+        .args(["--simulate", "--no-playlist", "--", LOGIN_PROBE_URL])
+        .status()?;
+
+    if status.success() {
+        println!(
+            "Logged in. Tokens are stored in {}; use --oauth to download members-only and private videos.",
+            cache_dir.display()
+        );
+    } else {
+        println!("Login failed with exit status: {}", status);
+    }
+    Ok(status.success())
+}
+
+pub fn is_logged_in(cache_dir: &Path) -> bool {
+    fs::read_dir(cache_dir).is_ok_and(|mut entries| entries.next().is_some())
+}
@@ -0,0 +1,177 @@
+use std::fs;
+use std::io::{self, BufRead, Write};
+use std::path::{Path, PathBuf};
+
+use crate::cli::Options;
+use crate::units::human_size;
+
+// Extensions of merged downloads, used to tell whether a `.f<format>` stream file was orphaned
+// by a merge that never finished.
+pub const MERGED_EXTENSIONS: &[&str] = &["mkv", "mp4", "webm", "mov", "m4a", "mp3", "opus", "ogg"];
+
+// Finds files crashed runs left behind in the output, staging and --move-to directories,
+// and deletes them after confirmation. Returns false when the user declined.
+pub fn clean(opts: &Options) -> io::Result<bool> {
+    let mut roots = vec![opts.staging_dir()];
+    roots.extend(opts.final_dir());
+    roots.dedup();
+
+    let mut leftovers = Vec::new();
+    for root in &roots {
+        for file in files_below(root)? {
+            if is_leftover(&file) {
+                let size = fs::metadata(&file).map_or(0, |m| m.len());
+                leftovers.push((file, size));
+            }
+        }
+    }
+
+    if leftovers.is_empty() {
+        println!("No leftover partial files found.");
+        return Ok(true);
+    }
+    for (file, size) in &leftovers {
+        println!("{:>10}  {}", human_size(*size), file.display());
+    }
+    let total: u64 = leftovers.iter().map(|(_, size)| size).sum();
+    println!(
+        "Found {} leftover files ({}).",
+        leftovers.len(),
+        human_size(total)
+    );
+
+    if !opts.yes && !confirm("Delete them?")? {
+        println!("Nothing deleted.");
+        return Ok(false);
+    }
+
+    let mut freed = 0;
+    let mut deleted = 0;
+    for (file, size) in &leftovers {
+        match fs::remove_file(file) {
+            Ok(()) => {
+                deleted += 1;
+                freed += size;
+            }
+            Err(err) => println!("Could not delete {}: {}", file.display(), err),
+        }
+    }
+    println!("Deleted {} files, freed {}.", deleted, human_size(freed));
+    Ok(true)
+}
+
+// Every file below `dir`, recursively. A missing directory has no files.
+pub fn files_below(dir: &Path) -> io::Result<Vec<PathBuf>> {
+    let mut files = Vec::new();
+    let mut pending = vec![dir.to_path_buf()];
+    while let Some(dir) = pending.pop() {
+        let entries = match fs::read_dir(&dir) {
+            Ok(entries) => entries,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => continue,
+            Err(err) => return Err(err),
+        };
+        for entry in entries {
+            let entry = entry?;
+            let file_type = entry.file_type()?;
+            if file_type.is_dir() {
+                pending.push(entry.path());
+            } else if file_type.is_file() {
+                files.push(entry.path());
+            }
+        }
+    }
+    files.sort();
+    Ok(files)
+}
+
+// yt-dlp's `.part`, `.ytdl` and `.temp.<ext>` files, fragment files of interrupted segmented
+// downloads, separate format streams whose merge never finished, and dlyt's own partial copies.
+pub fn is_leftover(file: &Path) -> bool {
+    let Some(name) = file.file_name().and_then(|n| n.to_str()) else {
+        return false;
+    };
+    if is_partial(file) || name.ends_with(".temp") || name.ends_with(".dlyt-tmp") {
+        return true;
+    }
+
+    let Some(stem) = file.file_stem().and_then(|s| s.to_str()) else {
+        return false;
+    };
+    // `.remux` is `dlyt remux` writing the converted file
+    if stem.ends_with(".temp") || stem.ends_with(".remux") {
+        return true;
+    }
+    // `title.f137.mp4` without a merged `title.mkv` (or similar) next to it
+    match stem.rsplit_once(".f") {
+        Some((base, format))
+            if !format.is_empty() && format.bytes().all(|b| b.is_ascii_digit()) =>
+        {
+            !MERGED_EXTENSIONS
+                .iter()
+                .any(|ext| file.with_file_name(format!("{}.{}", base, ext)).is_file())
+        }
+        _ => false,
+    }
+}
+
+// What an interrupted download continues from: yt-dlp's `.part` file with its `.ytdl` state and
+// fragments, or aria2c's `.aria2` control file.
+pub fn is_partial(file: &Path) -> bool {
+    file.file_name()
+        .and_then(|n| n.to_str())
+        .is_some_and(|name| {
+            name.ends_with(".part")
+                || name.ends_with(".ytdl")
+                || name.ends_with(".aria2")
+                || name.contains(".part-Frag")
+        })
+}
+
+// The partial files of one download, from the file names yt-dlp noted in `names_file` as it
+// started on them (see `download::names_file`): `Title.mkv` has `Title.f137.mp4.part`,
+// `Title.mkv.ytdl` and the like, and no other download's files.
+pub fn partial_files_of(names_file: &Path) -> io::Result<Vec<PathBuf>> {
+    let names = match fs::read_to_string(names_file) {
+        Ok(names) => names,
+        Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(err) => return Err(err),
+    };
+    let mut files = Vec::new();
+    for name in names.lines().map(Path::new) {
+        let (Some(dir), Some(stem)) = (name.parent(), name.file_stem().and_then(|s| s.to_str()))
+        else {
+            continue;
+        };
+        let prefix = format!("{}.", stem);
+        let dir = if dir.as_os_str().is_empty() {
+            Path::new(".")
+        } else {
+            dir
+        };
+        let entries = match fs::read_dir(dir) {
+            Ok(entries) => entries,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => continue,
+            Err(err) => return Err(err),
+        };
+        for entry in entries.filter_map(|entry| entry.ok()) {
+            let path = entry.path();
+            let ours = path
+                .file_name()
+                .and_then(|n| n.to_str())
+                .is_some_and(|n| n.starts_with(&prefix));
+            if ours && is_partial(&path) && !files.contains(&path) {
+                files.push(path);
+            }
+        }
+    }
+    files.sort();
+    Ok(files)
+}
+
+fn confirm(question: &str) -> io::Result<bool> {
+    print!("{} [y/N] ", question);
+    io::stdout().flush()?;
+    let mut answer = String::new();
+    io::stdin().lock().read_line(&mut answer)?;
+    Ok(matches!(answer.trim(), "y" | "Y" | "yes" | "Yes"))
+}
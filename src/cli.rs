@@ -0,0 +1,945 @@
+use std::path::{Path, PathBuf};
+use std::process::exit;
+
+use crate::collision::OnCollision;
+use crate::config::{self, Config};
+use crate::container::{self, Engine};
+use crate::deliver::SftpTarget;
+use crate::download::{Downloader, Metadata, Quality, Subtitles, Tier};
+use crate::du::SortBy;
+use crate::dupes::OnDuplicate;
+use crate::export::ExportFormat;
+use crate::failure::Policies;
+use crate::layout::Layout;
+use crate::notify::Notifiers;
+use crate::playlist::PlaylistOrder;
+use crate::run::Order;
+use crate::shared::SharedArchive;
+use crate::ytdlp::Channel;
+use crate::{aria2, date, paths, remux, units};
+use crate::{ARCHIVE_FILE, CONFIG_FILE, STAGING_DIR, URLS_DIR, VIDEOS_DIR};
+
+const USAGE: &str = "\
+Usage: dlyt [COMMAND] [OPTIONS]
+       dlyt remux [DIR] [--to mkv|mp4|webm]
+       dlyt playlist [DIR]
+       dlyt container-init [--container-engine docker|podman]
+
+Commands:
+  run       Download every URL listed in the .urls files of the urls directory (default)
+  history   List recorded downloads
+  verify    Check recorded downloads are still on disk and unchanged
+  daemon    Keep running: sync every --interval and retry failed URLs in between
+  login     Log in to YouTube once with a device code (yt-dlp OAuth2 plugin)
+  update    Install the latest yt-dlp for dlyt to use, checked against its published SHA-256,
+            or with --rollback go back to the one before
+  container-init
+            Write a Containerfile pinning yt-dlp, ffmpeg and aria2c, and build it for
+            --in-container
+  clean     Delete partial and temporary files left behind by interrupted runs
+  du        Show the disk space used per collection (.urls file)
+  dupes     List downloaded files with identical contents, hashing those recorded without --hash
+  stats     Summarize downloads per day and week, uploaders, failure rates and speed
+  export    Print the download history as CSV or JSON
+  listen    Accept URLs from a bookmarklet or browser extension on 127.0.0.1
+  remux     Convert downloaded files below DIR (default: the output) with --to, and embed
+            subtitles and chapters from sidecars, without downloading anything
+  playlist  Write an .m3u8 playlist into every directory below DIR (default: the output) that
+            has an index.json; `run` does this for the directories it saved videos in
+
+Options:
+      --output <DIR|URL>  Where videos go: a directory, or sftp://user@host/path [default: videos]
+      --temp-dir <DIR>    Download and post-process here before moving into place [default: .staging]
+      --move-to <DIR>     Move finished files and sidecars here once post-processing succeeded
+      --profile <NAME>    Apply the settings of the [profile.NAME] section of the config file
+      --quality <Q>       best, a maximum height such as 1080p, or audio [default: best]
+      --format <SELECTOR> yt-dlp format selector used verbatim, overriding --quality
+      --fallback <TIERS>  Qualities to step down to when a format fails, e.g. 1080p-mp4,best
+      --audio-lang <LANGS>
+                          Audio languages to prefer, in order, e.g. en,ja
+      --subtitles <S>     Subtitles to embed: auto, manual, all or none [default: auto]
+      --burn-subs <LANG>  Hardcode the subtitles in this language into the video after downloading
+      --burn-subs-separate
+                          Write the burned-in video next to the original instead of replacing it
+      --thumbnail         Save and embed the thumbnail
+      --metadata <M>      Description and tags: sidecar file, embed in the video, both or none [default: both]
+      --embed-chapters    Embed chapters [default: for videos of 20 minutes or more]
+      --no-embed-chapters Never embed chapters
+      --twitch-chapters   Embed the game segments of Twitch VODs as chapters and list them in
+                          Title.chapters.txt
+      --twitch-chat       Save the chat replay of Twitch VODs as Title.rechat.json
+      --downloader <D>    native, or aria2c for several connections per file [default: native]
+      --aria2c-connections <MIN-MAX>
+                          Bounds for aria2c's connections per file, tuned per host [default: 1-16]
+      --concurrent-fragments <N>
+                          Fragments of an HLS/DASH video fetched at once by yt-dlp's own
+                          downloader [default: 1, 4 for Twitch VODs]
+      --no-part           Write downloads straight to their file instead of a .part file
+      --discard-partial   Delete the partial files of failed and interrupted downloads instead
+                          of continuing from them
+      --skip-probe        Do not ask yt-dlp about URLs (-J) before downloading them
+      --probe-jobs <N>    URLs probed at the same time while downloads run [default: 4]
+      --normalize-audio   Normalize the loudness of downloaded audio (EBU R128, two-pass)
+      --keep-original     Keep the download as it was in originals/ when post-processing converts it
+      --upload <REMOTE>   Upload finished files and sidecars to an rclone remote or s3://bucket/path
+      --copy              Keep local files after uploading [default]
+      --move              Delete local files once their upload is verified
+      --proxy <URL>       Proxy for yt-dlp, e.g. socks5://127.0.0.1:1080
+      --cookies <FILE>    Cookies file used to retry videos that need an account
+      --cookies-from-browser <BROWSER>
+                          Browser to load cookies from when retrying videos that need an account
+      --oauth             Retry videos that need an account with the tokens from `dlyt login`
+      --interval <DURATION>
+                          Time between syncs in daemon mode [default: 6h]
+      --retry-backoff <DURATION>
+                          Wait before the first retry of a failed URL, doubled per attempt [default: 15m]
+      --retry-max-age <DURATION>
+                          Stop retrying a URL this long after its first failure [default: 2d]
+      --abort-on-error    Stop the run at the first URL that still fails after its retries
+      --mark-dead-after <N>
+                          Comment out URLs reported removed in N runs in a row
+      --comment-out-unavailable
+                          Comment out members-only, premium, private and removed videos in their .urls file
+      --max-downloads <N> Stop once N new files were downloaded; the rest waits for the next run
+      --metered           Prefer the smallest formats, for mobile data (see also --data-budget)
+      --data-budget <SIZE>
+                          Stop starting downloads once the run would go over this many bytes, e.g. 10G
+      --max-filesize <SIZE>
+                          Skip videos larger than this, e.g. 500M
+      --hash              Record an xxHash64 of every new file and check it against earlier downloads
+      --on-duplicate <D>  With --hash, what to do with a file already downloaded: warn or skip [default: warn]
+      --on-collision <C>  When a video would get the name of an existing file: suffix-id, skip,
+                          overwrite or error [default: suffix-id]
+      --estimate          Print the expected download size per directory before downloading
+      --dry-run           Print the estimate and stop without downloading anything
+      --order <O>         Download order: file, newest, oldest, shuffle or smallest-first [default: file]
+      --max-runtime <DURATION>
+                          Stop starting new downloads after this long, e.g. 90m, and exit with status 3
+      --resume            Continue with the URLs the last run stopped before, if it stopped early
+      --report-json <FILE>
+                          Write the run's results with failures per error class to FILE as JSON
+      --layout <L>        File layout: default, music (Artist/Album/NN - Title), shorts
+                          (shorts/<id>, several at a time), or auto to pick music for Bandcamp
+                          and SoundCloud and shorts for TikTok, Instagram and YouTube Shorts
+                          [default: default]
+      --shorts-jobs <N>   Short-form videos downloaded at the same time [default: 8]
+      --in-container      Run yt-dlp and ffmpeg in the image from `container-init` (or
+                          --container-image), with the directories they use mounted
+      --container-image <IMAGE>
+                          Image --in-container runs [default: dlyt-tools:latest]
+      --container-engine <E>
+                          docker or podman [default: podman if installed, else docker]
+      --porcelain         Print only a tab-separated result line per URL, for scripts
+      --progress-json     Print progress as JSON lines on stdout (phase, url, id, percent, speed, eta)
+      --port <PORT>       Port `listen` binds on 127.0.0.1 [default: 8765]
+      --token <TOKEN>     Token `listen` requires [default: generated, kept in listen.token]
+      --root <DIR>        Directory holding urls/ and the default output; relative paths start here [default: .]
+      --urls-dir <DIR>    Directory with the .urls files [default: urls]
+      --archive <FILE>    yt-dlp download archive [default: downloaded.txt in the state dir]
+      --shared-archive <LOCATION>
+                          Archive shared with other machines: a file on a network share, or redis://host
+      --no-archive-write  Skip the videos in the archive but do not add new ones to it
+      --no-archive        Ignore the archive: download known videos again and do not record new ones
+      --config <FILE>     Config file to read [default: dlyt.conf in the root if present, else the platform config dir]
+      --limit <N>         Number of entries `history` and `stats` list [default: 20]
+      --yes               Delete without asking for confirmation (`clean`)
+      --sort <KEY>        Order `du` by size, files, average or name [default: size]
+      --json              Print `du` and `history` as JSON
+      --format <csv|json> Output format of `export` [default: csv]
+      --since <DATE>      Only export downloads from this date (2024-05-01) on
+      --playlist-order <O>
+                          Order of the .m3u8 playlists: upload or download date [default: upload]
+      --to <CONTAINER>    Container `remux` converts into: mkv, mp4 or webm [default: keep]
+      --update-channel <C>
+                          Releases `update` installs: stable or nightly [default: stable]
+      --rollback          Make `update` go back to the yt-dlp the last update replaced
+  -h, --help              Print this help
+
+Durations are written like 90s, 15m, 6h or 2d.
+";
+
+// Options that are plain on/off switches and never consume a value.
+const SWITCHES: &[&str] = &[
+    "normalize-audio",
+    "keep-original",
+    "skip-probe",
+    "thumbnail",
+    "burn-subs-separate",
+    "embed-chapters",
+    "no-embed-chapters",
+    "no-part",
+    "discard-partial",
+    "rollback",
+    "in-container",
+    "twitch-chapters",
+    "twitch-chat",
+    "oauth",
+    "copy",
+    "move",
+    "yes",
+    "json",
+    "progress-json",
+    "porcelain",
+    "abort-on-error",
+    "comment-out-unavailable",
+    "no-archive",
+    "no-archive-write",
+    "resume",
+    "estimate",
+    "dry-run",
+    "metered",
+    "hash",
+];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Command {
+    Run,
+    History,
+    Verify,
+    Daemon,
+    Login,
+    Clean,
+    Update,
+    ContainerInit,
+    Du,
+    Dupes,
+    Stats,
+    Export,
+    Listen,
+    Remux,
+    Playlist,
+}
+
+#[derive(Debug, Clone)]
+pub struct Options {
+    pub command: Command,
+    pub root: PathBuf,
+    // Archive, history and other state, see `paths::state_dir`
+    pub state_dir: PathBuf,
+    pub urls_dir: PathBuf,
+    pub archive: Option<PathBuf>,
+    pub output: String,
+    pub temp_dir: Option<PathBuf>,
+    pub move_to: Option<PathBuf>,
+    pub profile: Option<String>,
+    pub quality: Quality,
+    pub fallback: Vec<Tier>,
+    pub shared_archive: Option<SharedArchive>,
+    pub no_archive: bool,
+    pub report_json: Option<PathBuf>,
+    // None: picked per site
+    pub layout: Option<Layout>,
+    pub no_archive_write: bool,
+    pub comment_out_unavailable: bool,
+    pub mark_dead_after: Option<usize>,
+    pub format: Option<String>,
+    pub audio_lang: Vec<String>,
+    pub subtitles: Subtitles,
+    pub thumbnail: bool,
+    pub twitch_chapters: bool,
+    pub twitch_chat: bool,
+    pub metadata: Metadata,
+    pub burn_subs: Option<String>,
+    pub burn_subs_separate: bool,
+    // None: only for long-form videos
+    pub embed_chapters: Option<bool>,
+    pub downloader: Downloader,
+    pub aria2c_connections: (u32, u32),
+    // None: yt-dlp's default, or more for Twitch VODs
+    pub concurrent_fragments: Option<u32>,
+    pub no_part: bool,
+    pub discard_partial: bool,
+    pub update_channel: Channel,
+    pub rollback: bool,
+    pub in_container: bool,
+    pub container_image: String,
+    // None: podman when installed, else docker
+    pub container_engine: Option<Engine>,
+    pub skip_probe: bool,
+    pub probe_jobs: usize,
+    pub shorts_jobs: usize,
+    pub normalize_audio: bool,
+    pub keep_original: bool,
+    pub upload: Option<String>,
+    pub upload_move: bool,
+    pub proxy: Option<String>,
+    pub cookies: Option<PathBuf>,
+    pub cookies_from_browser: Option<String>,
+    pub oauth: bool,
+    pub progress_json: bool,
+    pub porcelain: bool,
+    pub abort_on_error: bool,
+    pub max_downloads: Option<usize>,
+    pub max_runtime: Option<u64>,
+    pub order: Order,
+    pub estimate: bool,
+    pub metered: bool,
+    pub data_budget: Option<u64>,
+    pub max_filesize: Option<u64>,
+    pub hash: bool,
+    pub on_duplicate: OnDuplicate,
+    pub on_collision: OnCollision,
+    pub dry_run: bool,
+    pub resume: bool,
+    pub interval: u64,
+    pub retry_backoff: u64,
+    pub retry_max_age: u64,
+    pub policies: Policies,
+    pub limit: usize,
+    pub yes: bool,
+    pub sort: SortBy,
+    pub json: bool,
+    pub export_format: ExportFormat,
+    pub since: Option<u64>,
+    pub notify: Notifiers,
+    pub listen_port: u16,
+    pub listen_token: Option<String>,
+    // Directory `remux` and `playlist` work on
+    pub dir: Option<PathBuf>,
+    pub remux_to: Option<String>,
+    pub playlist_order: PlaylistOrder,
+}
+
+impl Default for Options {
+    fn default() -> Options {
+        Options {
+            command: Command::Run,
+            root: PathBuf::from("."),
+            state_dir: PathBuf::from("."),
+            urls_dir: PathBuf::from(URLS_DIR),
+            archive: None,
+            output: VIDEOS_DIR.to_string(),
+            temp_dir: None,
+            move_to: None,
+            profile: None,
+            quality: Quality::Best,
+            fallback: Vec::new(),
+            shared_archive: None,
+            no_archive: false,
+            report_json: None,
+            layout: Some(Layout::Default),
+            no_archive_write: false,
+            comment_out_unavailable: false,
+            mark_dead_after: None,
+            format: None,
+            audio_lang: Vec::new(),
+            subtitles: Subtitles::Auto,
+            thumbnail: false,
+            twitch_chapters: false,
+            twitch_chat: false,
+            metadata: Metadata::Both,
+            burn_subs: None,
+            burn_subs_separate: false,
+            embed_chapters: None,
+            downloader: Downloader::Native,
+            aria2c_connections: (1, aria2::MAX_CONNECTIONS),
+            concurrent_fragments: None,
+            no_part: false,
+            discard_partial: false,
+            update_channel: Channel::Stable,
+            rollback: false,
+            in_container: false,
+            container_image: container::DEFAULT_IMAGE.to_string(),
+            container_engine: None,
+            skip_probe: false,
+            probe_jobs: 4,
+            shorts_jobs: 8,
+            normalize_audio: false,
+            keep_original: false,
+            upload: None,
+            upload_move: false,
+            proxy: None,
+            cookies: None,
+            cookies_from_browser: None,
+            oauth: false,
+            progress_json: false,
+            porcelain: false,
+            abort_on_error: false,
+            max_downloads: None,
+            max_runtime: None,
+            order: Order::File,
+            estimate: false,
+            metered: false,
+            data_budget: None,
+            max_filesize: None,
+            hash: false,
+            on_duplicate: OnDuplicate::Warn,
+            on_collision: OnCollision::SuffixId,
+            dry_run: false,
+            resume: false,
+            interval: 6 * 3600,
+            retry_backoff: 15 * 60,
+            retry_max_age: 2 * 86_400,
+            policies: Policies::default(),
+            limit: 20,
+            yes: false,
+            sort: SortBy::Size,
+            json: false,
+            export_format: ExportFormat::Csv,
+            since: None,
+            notify: Notifiers::default(),
+            listen_port: 8765,
+            listen_token: None,
+            dir: None,
+            remux_to: None,
+            playlist_order: PlaylistOrder::Upload,
+        }
+    }
+}
+
+impl Options {
+    // Defaults, overridden by the config file, overridden by DLYT_* environment variables,
+    // overridden by the command line.
+    pub fn from_args() -> Result<Options, String> {
+        let (command, flags) = parse_args(std::env::args().skip(1))?;
+        let env = env_settings();
+
+        let mut opts = Options {
+            command,
+            ..Options::default()
+        };
+        let last_flag = |name: &str| {
+            flags
+                .iter()
+                .rev()
+                .find(|(key, _)| key == name)
+                .and_then(|(_, value)| value.clone())
+                .or_else(|| {
+                    env.iter()
+                        .find(|(_, key, _)| key == name)
+                        .map(|(_, _, value)| value.clone())
+                })
+        };
+        let root = last_flag("root").map_or_else(|| PathBuf::from("."), PathBuf::from);
+        let config_file =
+            last_flag("config").map_or_else(|| default_config_file(&root), PathBuf::from);
+        let profile = last_flag("profile");
+        match config::load(&config_file)? {
+            Some(config) => {
+                opts.apply_config(&config, profile)?;
+                opts.notify
+                    .validate()
+                    .map_err(|err| format!("{}: {}", config_file.display(), err))?;
+            }
+            None if profile.is_some() => {
+                return Err(format!(
+                    "--profile needs a [profile.NAME] section in {}, which does not exist",
+                    config_file.display()
+                ));
+            }
+            None => {}
+        }
+
+        for (variable, key, value) in &env {
+            match opts.set(key, Some(value)) {
+                // Set for something else, such as a container image's DLYT_VERSION
+                Err(err) if err == unknown_option(key) => {
+                    eprintln!("Ignoring {}: dlyt has no option --{}.", variable, key);
+                }
+                result => result.map_err(|err| format!("{}: {}", variable, err))?,
+            }
+        }
+        for (key, value) in flags {
+            opts.set(&key, value.as_deref())?;
+        }
+        if opts.skip_probe && (opts.estimate || opts.dry_run) {
+            return Err("--estimate and --dry-run need the URLs probed, so they cannot be combined with --skip-probe".to_string());
+        }
+        if opts.skip_probe && opts.order.needs_probe() {
+            return Err("--order newest, oldest and smallest-first need the URLs probed, so they cannot be combined with --skip-probe".to_string());
+        }
+        // Ignoring the archive includes the shared one
+        if opts.no_archive {
+            opts.shared_archive = None;
+        }
+        Ok(opts)
+    }
+
+    // Global settings, then the selected profile's section (from the command line, or a
+    // `profile` global), then the other sections.
+    fn apply_config(&mut self, config: &Config, profile: Option<String>) -> Result<(), String> {
+        for setting in &config.global {
+            self.set(&setting.key, Some(&setting.value))
+                .map_err(|err| format!("{}: {}", setting.origin, err))?;
+        }
+
+        if let Some(profile) = profile.or_else(|| self.profile.clone()) {
+            let name = format!("profile.{}", profile);
+            let section = config
+                .sections
+                .iter()
+                .find(|section| section.name == name)
+                .ok_or_else(|| {
+                    format!(
+                        "Unknown profile `{}`: there is no [{}] section",
+                        profile, name
+                    )
+                })?;
+            for setting in &section.settings {
+                if setting.key == "profile" {
+                    return Err(format!(
+                        "{}: profiles cannot select other profiles",
+                        setting.origin
+                    ));
+                }
+                self.set(&setting.key, Some(&setting.value))
+                    .map_err(|err| format!("{}: {}", setting.origin, err))?;
+            }
+            self.profile = Some(profile);
+        }
+
+        for section in &config.sections {
+            if section.name.starts_with("profile.") {
+                continue;
+            }
+            for setting in &section.settings {
+                let result = if let Some(class) = section.name.strip_prefix("on-error.") {
+                    self.policies.set(class, &setting.key, &setting.value)
+                } else if let Some(backend) = section.name.strip_prefix("notify.") {
+                    self.notify.set(backend, &setting.key, &setting.value)
+                } else {
+                    return Err(format!(
+                        "{}: unknown section [{}]",
+                        section.origin, section.name
+                    ));
+                };
+                result.map_err(|err| format!("{}: {}", setting.origin, err))?;
+            }
+        }
+        Ok(())
+    }
+
+    pub fn set(&mut self, key: &str, value: Option<&str>) -> Result<(), String> {
+        match key {
+            // Already handled before the config file was loaded
+            "config" => {}
+            "root" => self.root = PathBuf::from(value.unwrap_or(".")),
+            "urls-dir" => self.urls_dir = PathBuf::from(value.unwrap_or(URLS_DIR)),
+            "archive" => self.archive = value.map(PathBuf::from),
+            "layout" => {
+                let value = value.unwrap_or_default();
+                self.layout = match value {
+                    "auto" => None,
+                    _ => Some(Layout::from_name(value).ok_or_else(|| {
+                        format!(
+                            "Invalid value for --layout: {} (default, music, shorts or auto)",
+                            value
+                        )
+                    })?),
+                };
+            }
+            "report-json" => self.report_json = value.filter(|v| !v.is_empty()).map(PathBuf::from),
+            "no-archive" => self.no_archive = parse_bool(key, value)?,
+            "no-archive-write" => self.no_archive_write = parse_bool(key, value)?,
+            "shared-archive" => {
+                self.shared_archive = value
+                    .filter(|location| !location.is_empty())
+                    .map(SharedArchive::parse)
+            }
+            "proxy" => self.proxy = value.map(str::to_string),
+            "output" => self.output = value.unwrap_or(VIDEOS_DIR).to_string(),
+            "temp-dir" => self.temp_dir = value.map(PathBuf::from),
+            "move-to" => self.move_to = value.map(PathBuf::from),
+            // Picked before the config file is applied, see `apply_config`
+            "profile" => self.profile = value.map(str::to_string),
+            "quality" => {
+                let value = value.unwrap_or_default();
+                self.quality = Quality::from_name(value)
+                    .ok_or_else(|| format!("Invalid value for --quality: {}", value))?;
+            }
+            "comment-out-unavailable" => self.comment_out_unavailable = parse_bool(key, value)?,
+            "mark-dead-after" => {
+                let runs = parse_number(key, value)?;
+                if runs == 0 {
+                    return Err("--mark-dead-after must be at least 1".to_string());
+                }
+                self.mark_dead_after = Some(runs);
+            }
+            "fallback" => {
+                self.fallback = value
+                    .unwrap_or_default()
+                    .split(',')
+                    .map(str::trim)
+                    .filter(|name| !name.is_empty())
+                    .map(|name| {
+                        Tier::from_name(name).ok_or_else(|| {
+                            format!(
+                                "Invalid quality in --fallback: {} (e.g. 1080p, 720p-mp4, best or audio)",
+                                name
+                            )
+                        })
+                    })
+                    .collect::<Result<_, _>>()?;
+            }
+            "format" => self.format = value.map(str::to_string).filter(|f| !f.is_empty()),
+            "audio-lang" => {
+                self.audio_lang = value
+                    .unwrap_or_default()
+                    .split(',')
+                    .map(str::trim)
+                    .filter(|language| !language.is_empty())
+                    .map(str::to_string)
+                    .collect();
+                if let Some(invalid) = self.audio_lang.iter().find(|language| {
+                    !language
+                        .chars()
+                        .all(|c| c.is_ascii_alphanumeric() || c == '-')
+                }) {
+                    return Err(format!("Invalid language for --audio-lang: {}", invalid));
+                }
+            }
+            "subtitles" => {
+                let value = value.unwrap_or_default();
+                self.subtitles = Subtitles::from_name(value)
+                    .ok_or_else(|| format!("Invalid value for --subtitles: {}", value))?;
+            }
+            "thumbnail" => self.thumbnail = parse_bool(key, value)?,
+            "twitch-chapters" => self.twitch_chapters = parse_bool(key, value)?,
+            "twitch-chat" => self.twitch_chat = parse_bool(key, value)?,
+            "metadata" => {
+                let value = value.unwrap_or_default();
+                self.metadata = Metadata::from_name(value)
+                    .ok_or_else(|| format!("Invalid value for --metadata: {}", value))?;
+            }
+            "burn-subs" => {
+                self.burn_subs = value.map(str::to_string).filter(|l| !l.is_empty());
+                if let Some(language) = &self.burn_subs {
+                    if !language
+                        .chars()
+                        .all(|c| c.is_ascii_alphanumeric() || c == '-')
+                    {
+                        return Err(format!("Invalid language for --burn-subs: {}", language));
+                    }
+                }
+            }
+            "burn-subs-separate" => self.burn_subs_separate = parse_bool(key, value)?,
+            "embed-chapters" => self.embed_chapters = Some(parse_bool(key, value)?),
+            "no-embed-chapters" => self.embed_chapters = Some(!parse_bool(key, value)?),
+            "downloader" => {
+                let value = value.unwrap_or_default();
+                self.downloader = Downloader::from_name(value)
+                    .ok_or_else(|| format!("Invalid value for --downloader: {}", value))?;
+            }
+            "aria2c-connections" => {
+                let value = value.unwrap_or_default();
+                let (min, max) = value.split_once('-').unwrap_or((value, value));
+                self.aria2c_connections = match (min.parse(), max.parse()) {
+                    (Ok(min), Ok(max))
+                        if 1 <= min && min <= max && max <= aria2::MAX_CONNECTIONS =>
+                    {
+                        (min, max)
+                    }
+                    _ => {
+                        return Err(format!(
+                            "Invalid value for --aria2c-connections: {} (e.g. 2-8, at most {})",
+                            value,
+                            aria2::MAX_CONNECTIONS
+                        ))
+                    }
+                };
+            }
+            "concurrent-fragments" => {
+                let fragments = parse_number(key, value)?;
+                if fragments == 0 {
+                    return Err("--concurrent-fragments must be at least 1".to_string());
+                }
+                self.concurrent_fragments = Some(fragments);
+            }
+            "no-part" => self.no_part = parse_bool(key, value)?,
+            "discard-partial" => self.discard_partial = parse_bool(key, value)?,
+            "update-channel" => {
+                let value = value.unwrap_or_default();
+                self.update_channel = Channel::from_name(value).ok_or_else(|| {
+                    format!(
+                        "Invalid value for --update-channel: {} (stable or nightly)",
+                        value
+                    )
+                })?;
+            }
+            "rollback" => self.rollback = parse_bool(key, value)?,
+            "in-container" => self.in_container = parse_bool(key, value)?,
+            "container-image" => {
+                self.container_image = value.unwrap_or_default().to_string();
+                if self.container_image.is_empty() {
+                    return Err("--container-image needs an image name".to_string());
+                }
+            }
+            "container-engine" => {
+                let value = value.unwrap_or_default();
+                self.container_engine = Some(Engine::from_name(value).ok_or_else(|| {
+                    format!(
+                        "Invalid value for --container-engine: {} (docker or podman)",
+                        value
+                    )
+                })?);
+            }
+            "skip-probe" => self.skip_probe = parse_bool(key, value)?,
+            "probe-jobs" => {
+                self.probe_jobs = parse_number(key, value)?;
+                if self.probe_jobs == 0 {
+                    return Err("--probe-jobs must be at least 1".to_string());
+                }
+            }
+            "shorts-jobs" => {
+                self.shorts_jobs = parse_number(key, value)?;
+                if self.shorts_jobs == 0 {
+                    return Err("--shorts-jobs must be at least 1".to_string());
+                }
+            }
+            "normalize-audio" => self.normalize_audio = parse_bool(key, value)?,
+            "keep-original" => self.keep_original = parse_bool(key, value)?,
+            "upload" => self.upload = value.map(str::to_string),
+            "copy" => self.upload_move = !parse_bool(key, value)?,
+            "move" => self.upload_move = parse_bool(key, value)?,
+            "cookies" => self.cookies = value.map(PathBuf::from),
+            "cookies-from-browser" => self.cookies_from_browser = value.map(str::to_string),
+            "oauth" => self.oauth = parse_bool(key, value)?,
+            "progress-json" => self.progress_json = parse_bool(key, value)?,
+            "porcelain" => self.porcelain = parse_bool(key, value)?,
+            "abort-on-error" => self.abort_on_error = parse_bool(key, value)?,
+            "max-downloads" => {
+                let max = parse_number(key, value)?;
+                if max == 0 {
+                    return Err("--max-downloads must be at least 1".to_string());
+                }
+                self.max_downloads = Some(max);
+            }
+            "order" => {
+                let value = value.unwrap_or_default();
+                self.order = Order::from_name(value).ok_or_else(|| {
+                    format!(
+                        "Invalid value for --order: {} (file, newest, oldest, shuffle or smallest-first)",
+                        value
+                    )
+                })?;
+            }
+            "estimate" => self.estimate = parse_bool(key, value)?,
+            "metered" => self.metered = parse_bool(key, value)?,
+            "data-budget" => self.data_budget = Some(parse_size(key, value)?),
+            "max-filesize" => self.max_filesize = Some(parse_size(key, value)?),
+            "hash" => self.hash = parse_bool(key, value)?,
+            "on-duplicate" => {
+                let value = value.unwrap_or_default();
+                self.on_duplicate = OnDuplicate::from_name(value).ok_or_else(|| {
+                    format!("Invalid value for --on-duplicate: {} (warn or skip)", value)
+                })?;
+            }
+            "on-collision" => {
+                let value = value.unwrap_or_default();
+                self.on_collision = OnCollision::from_name(value).ok_or_else(|| {
+                    format!(
+                        "Invalid value for --on-collision: {} (suffix-id, skip, overwrite or error)",
+                        value
+                    )
+                })?;
+            }
+            "dry-run" => self.dry_run = parse_bool(key, value)?,
+            "max-runtime" => self.max_runtime = Some(parse_duration(key, value)?),
+            "resume" => self.resume = parse_bool(key, value)?,
+            "interval" => self.interval = parse_duration(key, value)?,
+            "retry-backoff" => self.retry_backoff = parse_duration(key, value)?,
+            "retry-max-age" => self.retry_max_age = parse_duration(key, value)?,
+            "limit" => self.limit = parse_number(key, value)?,
+            "yes" => self.yes = parse_bool(key, value)?,
+            "sort" => {
+                let value = value.unwrap_or_default();
+                self.sort = SortBy::from_name(value)
+                    .ok_or_else(|| format!("Invalid value for --sort: {}", value))?;
+            }
+            "json" => self.json = parse_bool(key, value)?,
+            "export-format" => {
+                let value = value.unwrap_or_default();
+                self.export_format = ExportFormat::from_name(value)
+                    .ok_or_else(|| format!("Invalid value for --format: {}", value))?;
+            }
+            "port" => self.listen_port = parse_number(key, value)?,
+            "token" => self.listen_token = value.map(str::to_string),
+            "dir" => self.dir = value.map(PathBuf::from),
+            "to" => {
+                let value = value.unwrap_or_default();
+                if !remux::CONTAINERS.contains(&value) {
+                    return Err(format!("Invalid value for --to: {}", value));
+                }
+                self.remux_to = Some(value.to_string());
+            }
+            "playlist-order" => {
+                let value = value.unwrap_or_default();
+                self.playlist_order = PlaylistOrder::from_name(value)
+                    .ok_or_else(|| format!("Invalid value for --playlist-order: {}", value))?;
+            }
+            "since" => {
+                let value = value.unwrap_or_default();
+                self.since = Some(
+                    date::parse_date(value)
+                        .ok_or_else(|| format!("Invalid date for --since: {}", value))?,
+                );
+            }
+            _ => return Err(unknown_option(key)),
+        }
+        Ok(())
+    }
+
+    pub fn state_file(&self, name: &str) -> PathBuf {
+        self.state_dir.join(name)
+    }
+
+    pub fn archive_file(&self) -> PathBuf {
+        self.archive
+            .clone()
+            .unwrap_or_else(|| self.state_file(ARCHIVE_FILE))
+    }
+
+    pub fn sftp_target(&self) -> Option<SftpTarget> {
+        SftpTarget::parse(&self.output)
+    }
+
+    // Directory yt-dlp downloads into. Finished files only leave it once post-processing
+    // succeeded; for an SFTP destination they are sent from here.
+    pub fn staging_dir(&self) -> PathBuf {
+        match &self.temp_dir {
+            Some(dir) => dir.clone(),
+            None if self.output.starts_with("sftp://") => std::env::temp_dir().join("dlyt-sftp"),
+            None => PathBuf::from(STAGING_DIR),
+        }
+    }
+
+    // Local directory finished files are renamed into: --move-to, or the output directory.
+    // None for an SFTP destination.
+    pub fn final_dir(&self) -> Option<PathBuf> {
+        match &self.move_to {
+            Some(dir) => Some(dir.clone()),
+            None if self.output.starts_with("sftp://") => None,
+            None => Some(PathBuf::from(&self.output)),
+        }
+    }
+
+    pub fn has_credentials(&self) -> bool {
+        self.cookies.is_some() || self.cookies_from_browser.is_some() || self.oauth
+    }
+}
+
+// `DLYT_*` environment variables as (variable, option key, value): `DLYT_RETRY_BACKOFF=1h` is
+// `--retry-backoff 1h`, and `DLYT_OUTPUT_DIR` is accepted for `--output`.
+fn env_settings() -> Vec<(String, String, String)> {
+    let mut settings: Vec<(String, String, String)> = std::env::vars()
+        .filter_map(|(variable, value)| {
+            let name = variable.strip_prefix("DLYT_")?;
+            let key = match name {
+                "OUTPUT_DIR" => "output".to_string(),
+                _ => name.to_ascii_lowercase().replace('_', "-"),
+            };
+            Some((variable, key, value))
+        })
+        .collect();
+    settings.sort();
+    settings
+}
+
+// `dlyt.conf` in the root if there is one, otherwise in the platform config directory.
+fn default_config_file(root: &Path) -> PathBuf {
+    let in_root = root.join(CONFIG_FILE);
+    match paths::config_dir() {
+        Some(dir) if !in_root.exists() => dir.join(CONFIG_FILE),
+        _ => in_root,
+    }
+}
+
+fn unknown_option(key: &str) -> String {
+    format!("Unknown option: --{}", key)
+}
+
+// A `--key[=value]` flag from the command line, value None for bare switches.
+type Flag = (String, Option<String>);
+
+// Splits the command line into the command and its flags.
+fn parse_args<I: Iterator<Item = String>>(args: I) -> Result<(Command, Vec<Flag>), String> {
+    let mut args = args.peekable();
+    let mut command = Command::Run;
+    let mut flags = Vec::new();
+
+    if let Some(name) = args.next_if(|arg| !arg.starts_with('-')) {
+        command = match name.as_str() {
+            "run" => Command::Run,
+            "history" => Command::History,
+            "verify" => Command::Verify,
+            "daemon" => Command::Daemon,
+            "login" => Command::Login,
+            "clean" => Command::Clean,
+            "update" => Command::Update,
+            "container-init" => Command::ContainerInit,
+            "du" => Command::Du,
+            "dupes" => Command::Dupes,
+            "stats" => Command::Stats,
+            "export" => Command::Export,
+            "listen" => Command::Listen,
+            "remux" => Command::Remux,
+            "playlist" => Command::Playlist,
+            _ => return Err(format!("Unknown command: {}", name)),
+        };
+    }
+
+    while let Some(arg) = args.next() {
+        if arg == "-h" || arg == "--help" {
+            print!("{}", USAGE);
+            exit(0);
+        }
+
+        let flag = match arg.strip_prefix("--") {
+            Some(flag) => flag,
+            // `dlyt remux <DIR>`, `dlyt playlist <DIR>`
+            None if matches!(command, Command::Remux | Command::Playlist)
+                && !flags.iter().any(|(key, _)| key == "dir") =>
+            {
+                flags.push(("dir".to_string(), Some(arg)));
+                continue;
+            }
+            None => return Err(format!("Unexpected argument: {}", arg)),
+        };
+        let (key, inline) = match flag.split_once('=') {
+            Some((key, value)) => (key, Some(value.to_string())),
+            None => (flag, None),
+        };
+
+        let value = if SWITCHES.contains(&key) || inline.is_some() {
+            inline
+        } else {
+            match args.next() {
+                Some(value) => Some(value),
+                None => return Err(format!("Option --{} requires a value", key)),
+            }
+        };
+        // `export --format` picks the output format, see `export-format`
+        let key = match key {
+            "format" if command == Command::Export => "export-format",
+            _ => key,
+        };
+        flags.push((key.to_string(), value));
+    }
+
+    Ok((command, flags))
+}
+
+fn parse_bool(key: &str, value: Option<&str>) -> Result<bool, String> {
+    match value {
+        None | Some("true") | Some("yes") | Some("on") | Some("1") => Ok(true),
+        Some("false") | Some("no") | Some("off") | Some("0") => Ok(false),
+        Some(other) => Err(format!("Invalid value for --{}: {}", key, other)),
+    }
+}
+
+fn parse_number<T: std::str::FromStr>(key: &str, value: Option<&str>) -> Result<T, String> {
+    let value = value.unwrap_or_default();
+    value
+        .parse()
+        .map_err(|_| format!("Invalid number for --{}: {}", key, value))
+}
+
+fn parse_size(key: &str, value: Option<&str>) -> Result<u64, String> {
+    let value = value.unwrap_or_default();
+    units::parse_size(value).ok_or_else(|| format!("Invalid size for --{}: {}", key, value))
+}
+
+fn parse_duration(key: &str, value: Option<&str>) -> Result<u64, String> {
+    let value = value.unwrap_or_default();
+    units::parse_duration(value).ok_or_else(|| format!("Invalid duration for --{}: {}", key, value))
+}
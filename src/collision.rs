@@ -0,0 +1,60 @@
+use std::path::PathBuf;
+
+use crate::clean::MERGED_EXTENSIONS;
+
+// What a run does with a video that would be saved under the name of an existing file, e.g.
+// two videos with the same title, from `--on-collision`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OnCollision {
+    // Save it as `<title> [<id>].<ext>` instead
+    SuffixId,
+    // Leave it for later without downloading
+    Skip,
+    // Replace the existing file
+    Overwrite,
+    // Count it as a failed download
+    Error,
+}
+
+impl OnCollision {
+    pub fn from_name(name: &str) -> Option<OnCollision> {
+        match name {
+            "suffix-id" => Some(OnCollision::SuffixId),
+            "skip" => Some(OnCollision::Skip),
+            "overwrite" => Some(OnCollision::Overwrite),
+            "error" => Some(OnCollision::Error),
+            _ => None,
+        }
+    }
+}
+
+// The file name, without extension, yt-dlp makes of a title: characters file systems reject
+// become their full-width look-alikes, control characters are dropped, and a leading `-` or
+// `.` is defused.
+pub fn file_stem(title: &str) -> String {
+    let mut stem: String = title
+        .chars()
+        .filter_map(|c| match c {
+            '/' => Some('\u{29f8}'),
+            '\\' => Some('\u{29f9}'),
+            '"' | '*' | ':' | '<' | '>' | '?' | '|' => char::from_u32(c as u32 + 0xfee0),
+            '\n' => Some(' '),
+            c if c.is_control() => None,
+            c => Some(c),
+        })
+        .collect();
+    if let Some(rest) = stem.strip_prefix('-') {
+        stem = format!("_{}", rest);
+    }
+    stem.trim_start_matches('.').to_string()
+}
+
+// A video file named `stem` with any extension in one of the directories.
+pub fn existing(dirs: &[PathBuf], stem: &str) -> Option<PathBuf> {
+    dirs.iter().find_map(|dir| {
+        MERGED_EXTENSIONS
+            .iter()
+            .map(|ext| dir.join(format!("{}.{}", stem, ext)))
+            .find(|path| path.is_file())
+    })
+}
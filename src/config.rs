@@ -0,0 +1,81 @@
+use std::fs;
+use std::io;
+use std::path::Path;
+
+// Settings from the config file: global `key = value` pairs, named like the command-line flags
+// without their dashes, followed by `[section]`s.
+//
+//     normalize-audio = true
+//     interval = 12h
+//
+//     [on-error.removed]
+//     action = annotate
+#[derive(Debug, Default)]
+pub struct Config {
+    pub global: Vec<Setting>,
+    pub sections: Vec<Section>,
+}
+
+#[derive(Debug)]
+pub struct Section {
+    pub name: String,
+    pub origin: String,
+    pub settings: Vec<Setting>,
+}
+
+#[derive(Debug, Clone)]
+pub struct Setting {
+    pub key: String,
+    pub value: String,
+    // For error messages: `dlyt.conf:12`
+    pub origin: String,
+}
+
+// Returns None when the file does not exist.
+pub fn load(path: &Path) -> Result<Option<Config>, String> {
+    let contents = match fs::read_to_string(path) {
+        Ok(contents) => contents,
+        Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(None),
+        Err(err) => return Err(format!("Could not read {}: {}", path.display(), err)),
+    };
+
+    let mut config = Config::default();
+    for (index, line) in contents.lines().enumerate() {
+        let origin = format!("{}:{}", path.display(), index + 1);
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') || line.starts_with(';') {
+            continue;
+        }
+
+        if let Some(name) = line.strip_prefix('[').and_then(|l| l.strip_suffix(']')) {
+            config.sections.push(Section {
+                name: name.trim().to_string(),
+                origin,
+                settings: Vec::new(),
+            });
+            continue;
+        }
+
+        let (key, value) = line
+            .split_once('=')
+            .ok_or_else(|| format!("{}: expected `key = value`, found `{}`", origin, line))?;
+        let setting = Setting {
+            key: key.trim().to_string(),
+            value: unquote(value.trim()).to_string(),
+            origin,
+        };
+        match config.sections.last_mut() {
+            Some(section) => section.settings.push(setting),
+            None => config.global.push(setting),
+        }
+    }
+
+    Ok(Some(config))
+}
+
+fn unquote(value: &str) -> &str {
+    value
+        .strip_prefix('"')
+        .and_then(|v| v.strip_suffix('"'))
+        .unwrap_or(value)
+}
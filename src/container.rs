@@ -0,0 +1,358 @@
+use std::env;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use std::sync::OnceLock;
+
+use crate::cli::Options;
+use crate::{json, paths, ytdlp};
+use crate::{CONFIG_FILE, CONTAINER_DIR};
+
+// Written into the root by `dlyt container-init`, and read back on later runs of it, so the
+// versions it pins can be edited and checked in with the .urls files
+pub const CONTAINER_FILE: &str = "Containerfile";
+// Image `--in-container` runs when --container-image is not given; `container-init` tags the
+// image it builds with this as well as with the yt-dlp version
+pub const DEFAULT_IMAGE: &str = "dlyt-tools:latest";
+const IMAGE_NAME: &str = "dlyt-tools";
+// Python and Debian release the image is built on; ffmpeg and aria2c come from Debian's
+// packages. A new Containerfile pins the tag to the digest it points to when written.
+const BASE_IMAGE: &str = "docker.io/library/python:3.12-slim-bookworm";
+// yt-dlp's releases on PyPI, for the version a new Containerfile pins
+const PYPI_URL: &str = "https://pypi.org/pypi/yt-dlp/json";
+
+// Set by `init` with --in-container: yt-dlp and ffmpeg then run in this image.
+static CONTAINER: OnceLock<Container> = OnceLock::new();
+
+// The container engine, from `--container-engine`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Engine {
+    Docker,
+    Podman,
+}
+
+impl Engine {
+    pub fn from_name(name: &str) -> Option<Engine> {
+        match name {
+            "docker" => Some(Engine::Docker),
+            "podman" => Some(Engine::Podman),
+            _ => None,
+        }
+    }
+
+    fn program(self) -> &'static str {
+        match self {
+            Engine::Docker => "docker",
+            Engine::Podman => "podman",
+        }
+    }
+
+    // The one asked for, else podman when it is installed, else docker.
+    fn find(configured: Option<Engine>) -> Result<(Engine, PathBuf), String> {
+        let candidates = match configured {
+            Some(engine) => vec![engine],
+            None => vec![Engine::Podman, Engine::Docker],
+        };
+        candidates
+            .into_iter()
+            .find_map(|engine| Some((engine, paths::find_program(engine.program())?)))
+            .ok_or_else(|| match configured {
+                Some(engine) => format!("{} is not installed.", engine.program()),
+                None => "Neither podman nor docker is installed.".to_string(),
+            })
+    }
+}
+
+#[derive(Debug)]
+struct Container {
+    engine: Engine,
+    program: PathBuf,
+    image: String,
+    // Host directories mounted at the same path inside, so every path dlyt passes works as is
+    mounts: Vec<PathBuf>,
+}
+
+// Switches yt-dlp and ffmpeg to the container for --in-container. Mounts the root, the state
+// directory, the temporary directory and every directory outside the root the options name.
+pub fn init(opts: &Options) -> Result<(), String> {
+    if cfg!(windows) {
+        return Err(
+            "--in-container needs the same paths inside the container as on the host, which \
+             Windows does not have; run dlyt in WSL instead."
+                .to_string(),
+        );
+    }
+    let (engine, program) = Engine::find(opts.container_engine)?;
+
+    let staging = opts.staging_dir();
+    let _ = fs::create_dir_all(&staging);
+    if let Some(dir) = &opts.move_to {
+        let _ = fs::create_dir_all(dir);
+    }
+    let mut dirs = vec![
+        PathBuf::from("."),
+        opts.state_dir.clone(),
+        env::temp_dir(),
+        staging,
+    ];
+    dirs.extend(opts.final_dir());
+    dirs.extend(opts.dir.clone());
+    dirs.extend(opts.archive_file().parent().map(Path::to_path_buf));
+    dirs.extend(
+        opts.cookies
+            .as_ref()
+            .and_then(|file| file.parent())
+            .map(Path::to_path_buf),
+    );
+
+    let mut dirs: Vec<PathBuf> = dirs
+        .into_iter()
+        .filter_map(|dir| {
+            let dir = if dir.as_os_str().is_empty() {
+                PathBuf::from(".")
+            } else {
+                dir
+            };
+            dir.canonicalize().ok()
+        })
+        .collect();
+    // Parents first, so directories inside one already mounted are left out
+    dirs.sort();
+    let mut mounts: Vec<PathBuf> = Vec::new();
+    for dir in dirs {
+        if !mounts.iter().any(|mount| dir.starts_with(mount)) {
+            mounts.push(dir);
+        }
+    }
+
+    let _ = CONTAINER.set(Container {
+        engine,
+        program,
+        image: opts.container_image.clone(),
+        mounts,
+    });
+    Ok(())
+}
+
+pub fn is_active() -> bool {
+    CONTAINER.get().is_some()
+}
+
+// `program` (yt-dlp, ffmpeg) in the container with --in-container, else the one on the host.
+pub fn command(program: &str) -> Command {
+    let Some(container) = CONTAINER.get() else {
+        return Command::new(program);
+    };
+    let mut command = Command::new(&container.program);
+    // --init so yt-dlp, as the container's first process, still stops on a signal
+    command.args([
+        "run",
+        "--rm",
+        "-i",
+        "--init",
+        "--security-opt",
+        "label=disable",
+    ]);
+    // Files written in the mounted directories belong to the user, not to the container's root
+    match container.engine {
+        Engine::Podman => {
+            command.arg("--userns=keep-id");
+        }
+        Engine::Docker => {
+            if let Some(user) = owner(Path::new(".")) {
+                command.args(["--user", &user, "-e", "HOME=/tmp"]);
+            }
+        }
+    }
+    // --mount rather than -v, which splits at every `:` in a path
+    for mount in &container.mounts {
+        let path = mount.display().to_string();
+        command.arg("--mount").arg(format!(
+            "type=bind,{},{}",
+            mount_field("src", &path),
+            mount_field("dst", &path)
+        ));
+    }
+    if let Ok(dir) = env::current_dir() {
+        command.arg("-w").arg(dir);
+    }
+    command
+        .args(["--entrypoint", program])
+        .arg(&container.image);
+    command
+}
+
+// `key=value` of a --mount, which is read as CSV: quoted when the path has a comma or quote.
+fn mount_field(key: &str, value: &str) -> String {
+    let field = format!("{}={}", key, value);
+    if field.contains([',', '"']) {
+        format!("\"{}\"", field.replace('"', "\"\""))
+    } else {
+        field
+    }
+}
+
+#[cfg(unix)]
+fn owner(dir: &Path) -> Option<String> {
+    use std::os::unix::fs::MetadataExt;
+    let metadata = fs::metadata(dir).ok()?;
+    Some(format!("{}:{}", metadata.uid(), metadata.gid()))
+}
+
+#[cfg(not(unix))]
+fn owner(_dir: &Path) -> Option<String> {
+    None
+}
+
+// Whether the --in-container image is there to run. Prints what to do when it is not.
+pub fn check_image() -> bool {
+    let Some(container) = CONTAINER.get() else {
+        return true;
+    };
+    let found = Command::new(&container.program)
+        .args(["image", "inspect"])
+        .arg(&container.image)
+        .stdin(Stdio::null())
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .status()
+        .is_ok_and(|status| status.success());
+    if !found {
+        println!(
+            "The image {} is not there. Build it with `dlyt container-init`, or pull one that has \
+             yt-dlp and ffmpeg and name it with --container-image.",
+            container.image
+        );
+    }
+    found
+}
+
+// `dlyt container-init`: writes CONTAINER_FILE pinning the current yt-dlp release unless there
+// is one, and builds it as `dlyt-tools:<yt-dlp version>` and DEFAULT_IMAGE. Returns false when
+// the build failed.
+pub fn build(opts: &Options) -> io::Result<bool> {
+    let (engine, program) = match Engine::find(opts.container_engine) {
+        Ok(found) => found,
+        Err(err) => {
+            println!("{}", err);
+            return Ok(false);
+        }
+    };
+    let file = Path::new(CONTAINER_FILE);
+    if !file.exists() {
+        let version = match latest_version() {
+            Ok(version) => version,
+            Err(err) => {
+                println!("Could not look up the latest yt-dlp release: {}", err);
+                return Ok(false);
+            }
+        };
+        let digest = match base_digest(&program) {
+            Ok(digest) => digest,
+            Err(err) => {
+                println!("Could not pull {}: {}", BASE_IMAGE, err);
+                return Ok(false);
+            }
+        };
+        fs::write(file, containerfile(&version, &digest))?;
+        println!(
+            "Wrote {} pinning yt-dlp {} on {}@{}.",
+            CONTAINER_FILE, version, BASE_IMAGE, digest
+        );
+    }
+    let Some(version) = pinned_version(&fs::read_to_string(file)?) else {
+        println!(
+            "{} has no `ARG YTDLP_VERSION=...` line to tag the image with.",
+            CONTAINER_FILE
+        );
+        return Ok(false);
+    };
+
+    // An empty build context, so the engine does not copy the videos over
+    let context = opts.state_file(CONTAINER_DIR);
+    fs::create_dir_all(&context)?;
+    let tag = format!("{}:{}", IMAGE_NAME, version);
+    println!("Building {} with {}", tag, engine.program());
+    let status = Command::new(&program)
+        .arg("build")
+        .args(["-t", &tag, "-t", DEFAULT_IMAGE, "-f"])
+        .arg(file)
+        .arg(&context)
+        .stdin(Stdio::null())
+        .status()?;
+    if !status.success() {
+        println!("The build failed.");
+        return Ok(false);
+    }
+    println!(
+        "Built {}. `dlyt run --in-container` uses {}; to stay on this yt-dlp when the image is \
+         rebuilt, put `container-image = {}` in {}.",
+        tag, DEFAULT_IMAGE, tag, CONFIG_FILE
+    );
+    Ok(true)
+}
+
+// The newest yt-dlp on PyPI, the version pip installs it by.
+fn latest_version() -> io::Result<String> {
+    let body = ytdlp::fetch(PYPI_URL, None)?;
+    let release = json::parse(&body).map_err(io::Error::other)?;
+    release
+        .get("info")
+        .and_then(|info| info.get("version"))
+        .and_then(|version| version.as_str())
+        .map(str::to_string)
+        .ok_or_else(|| io::Error::other("no version in PyPI's answer"))
+}
+
+// Pulls BASE_IMAGE and returns the digest its tag points to now, `sha256:...`.
+fn base_digest(program: &Path) -> io::Result<String> {
+    let status = Command::new(program)
+        .args(["pull", BASE_IMAGE])
+        .stdin(Stdio::null())
+        .status()?;
+    if !status.success() {
+        return Err(io::Error::other(format!("pull exited with {}", status)));
+    }
+    let output = Command::new(program)
+        .args([
+            "image",
+            "inspect",
+            "--format",
+            "{{index .RepoDigests 0}}",
+            BASE_IMAGE,
+        ])
+        .stdin(Stdio::null())
+        .output()?;
+    let digests = String::from_utf8_lossy(&output.stdout);
+    digests
+        .trim()
+        .split_once('@')
+        .map(|(_, digest)| digest.to_string())
+        .filter(|digest| digest.starts_with("sha256:"))
+        .ok_or_else(|| io::Error::other("the pulled image has no digest"))
+}
+
+fn pinned_version(containerfile: &str) -> Option<String> {
+    containerfile.lines().find_map(|line| {
+        let version = line.trim().strip_prefix("ARG YTDLP_VERSION=")?.trim();
+        (!version.is_empty()).then(|| version.to_string())
+    })
+}
+
+fn containerfile(version: &str, digest: &str) -> String {
+    format!(
+        "\
+# The tools `dlyt run --in-container` runs, written by `dlyt container-init`.
+# Change YTDLP_VERSION and run `dlyt container-init` again to move to another yt-dlp; delete
+# this file to also move to the current base image.
+FROM {}@{}
+ARG YTDLP_VERSION={}
+RUN apt-get update \\
+ && apt-get install -y --no-install-recommends ffmpeg aria2 ca-certificates \\
+ && rm -rf /var/lib/apt/lists/*
+RUN pip install --no-cache-dir \"yt-dlp[default]==${{YTDLP_VERSION}}\"
+",
+        BASE_IMAGE, digest, version
+    )
+}
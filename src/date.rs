@@ -0,0 +1,100 @@
+use std::time::{SystemTime, UNIX_EPOCH};
+
+pub fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0)
+}
+
+// Seconds since the epoch -> `2024-05-01T13:37:00Z`.
+pub fn format_timestamp(secs: u64) -> String {
+    let (year, month, day) = civil_from_days((secs / 86_400) as i64);
+    let rem = secs % 86_400;
+    format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}Z",
+        year,
+        month,
+        day,
+        rem / 3600,
+        rem % 3600 / 60,
+        rem % 60
+    )
+}
+
+// `2024-05-01T13:37:00Z` -> seconds since the epoch.
+pub fn parse_timestamp(timestamp: &str) -> Option<u64> {
+    let number = |range: std::ops::Range<usize>| timestamp.get(range)?.parse::<u64>().ok();
+    let days = days_from_civil(
+        number(0..4)? as i64,
+        number(5..7)? as u32,
+        number(8..10)? as u32,
+    );
+    let secs = number(11..13)? * 3600 + number(14..16)? * 60 + number(17..19)?;
+    u64::try_from(days).ok().map(|days| days * 86_400 + secs)
+}
+
+// `2024-05-01` (midnight UTC) or a full timestamp -> seconds since the epoch.
+pub fn parse_date(value: &str) -> Option<u64> {
+    match value.len() {
+        10 => parse_timestamp(&format!("{}T00:00:00Z", value)),
+        _ => parse_timestamp(value),
+    }
+}
+
+// Seconds since the epoch -> `2024-05-01`.
+pub fn format_date(secs: u64) -> String {
+    format_timestamp(secs)[..10].to_string()
+}
+
+// Seconds since the epoch -> `Thu, 01 May 2024 13:37:00 +0000`, for mail headers.
+pub fn format_rfc2822(secs: u64) -> String {
+    const WEEKDAYS: &[&str] = &["Thu", "Fri", "Sat", "Sun", "Mon", "Tue", "Wed"];
+    const MONTHS: &[&str] = &[
+        "Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct", "Nov", "Dec",
+    ];
+    let days = secs / 86_400;
+    let (year, month, day) = civil_from_days(days as i64);
+    let rem = secs % 86_400;
+    format!(
+        "{}, {:02} {} {:04} {:02}:{:02}:{:02} +0000",
+        WEEKDAYS[(days % 7) as usize],
+        day,
+        MONTHS[month as usize - 1],
+        year,
+        rem / 3600,
+        rem % 3600 / 60,
+        rem % 60
+    )
+}
+
+// Start of the Monday-based week containing `secs`. The epoch was a Thursday.
+pub fn week_start(secs: u64) -> u64 {
+    let days = secs / 86_400;
+    (days - (days + 3) % 7) * 86_400
+}
+
+// Howard Hinnant's days-to-civil algorithm for the proleptic Gregorian calendar.
+fn civil_from_days(days: i64) -> (i64, u32, u32) {
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z.rem_euclid(146_097);
+    let yoe = (doe - doe / 1460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = (doy - (153 * mp + 2) / 5 + 1) as u32;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 } as u32;
+    let year = yoe + era * 400 + i64::from(month <= 2);
+    (year, month, day)
+}
+
+// The inverse of `civil_from_days`.
+fn days_from_civil(year: i64, month: u32, day: u32) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let yoe = year.rem_euclid(400);
+    let mp = i64::from((month + 9) % 12);
+    let doy = (153 * mp + 2) / 5 + i64::from(day) - 1;
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+    era * 146_097 + doe - 719_468
+}
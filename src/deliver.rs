@@ -0,0 +1,311 @@
+use std::fs;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+
+// One file handed to a destination after its download finished.
+pub struct Transfer {
+    pub file: PathBuf,
+    pub destination: String,
+    pub size: u64,
+    pub result: Result<(), String>,
+}
+
+// Extensions of subtitle sidecars, `Title.<lang>.<ext>`; Twitch chat comes as `rechat.json`
+const SUBTITLE_EXTENSIONS: &[&str] = &["vtt", "srt", "ass", "ttml", "json"];
+
+// Files yt-dlp wrote next to a download: `Title.description`, `Title.en.vtt`, `Title.jpg`, ...
+// Only those forms count, so `Title. Part 2.mkv` is not a sidecar of `Title.mkv`.
+pub fn sidecars(path: &Path) -> Vec<PathBuf> {
+    let (Some(dir), Some(stem)) = (path.parent(), path.file_stem().and_then(|s| s.to_str())) else {
+        return Vec::new();
+    };
+    let prefix = format!("{}.", stem);
+
+    let Ok(entries) = fs::read_dir(if dir.as_os_str().is_empty() {
+        Path::new(".")
+    } else {
+        dir
+    }) else {
+        return Vec::new();
+    };
+    let mut found: Vec<PathBuf> = entries
+        .filter_map(|entry| entry.ok())
+        .map(|entry| dir.join(entry.file_name()))
+        .filter(|candidate| {
+            candidate != path
+                && candidate.is_file()
+                && candidate
+                    .file_name()
+                    .and_then(|name| name.to_str())
+                    .and_then(|name| name.strip_prefix(&prefix))
+                    .is_some_and(is_sidecar_suffix)
+        })
+        .collect();
+    found.sort();
+    found
+}
+
+// What follows `Title.` in a sidecar's name: an extension (`jpg`, `description`), `info.json`,
+// `chapters.txt`, or `<lang>.<ext>` for subtitles and `<lang>-burned.<ext>` for --burn-subs.
+fn is_sidecar_suffix(suffix: &str) -> bool {
+    let is_word = |word: &str| {
+        !word.is_empty()
+            && word
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+    };
+    match suffix.split_once('.') {
+        None => is_word(suffix),
+        Some((first, ext)) if is_word(first) && is_word(ext) => {
+            matches!(suffix, "info.json" | "chapters.txt" | "chapters.json")
+                || SUBTITLE_EXTENSIONS.contains(&ext)
+                || first.ends_with("-burned")
+        }
+        Some(_) => false,
+    }
+}
+
+// Moves a file to `target` so that `target` never exists half-written: a plain rename on the
+// same filesystem, otherwise a copy to a hidden name next to `target` that is then renamed.
+pub fn move_file(file: &Path, target: &Path) -> Transfer {
+    let size = fs::metadata(file).map_or(0, |m| m.len());
+    let result = (|| {
+        let parent = target.parent().unwrap_or(Path::new("."));
+        fs::create_dir_all(parent)?;
+        if fs::rename(file, target).is_ok() {
+            return Ok(());
+        }
+
+        let name = target.file_name().unwrap_or_default().to_string_lossy();
+        let partial = parent.join(format!(".{}.dlyt-tmp", name));
+        if let Err(err) = fs::copy(file, &partial).and_then(|_| fs::rename(&partial, target)) {
+            let _ = fs::remove_file(&partial);
+            return Err(err);
+        }
+        fs::remove_file(file)
+    })()
+    .map_err(|err: io::Error| err.to_string());
+
+    Transfer {
+        file: file.to_path_buf(),
+        destination: target.to_string_lossy().into_owned(),
+        size,
+        result,
+    }
+}
+
+// `s3://bucket/prefix` becomes an rclone on-the-fly S3 remote using the usual AWS environment
+// credentials; anything else is taken to be an rclone remote such as `gdrive:archive`.
+pub fn rclone_remote(destination: &str) -> String {
+    match destination.strip_prefix("s3://") {
+        Some(bucket_path) => format!(":s3,env_auth=true:{}", bucket_path),
+        None => destination.to_string(),
+    }
+}
+
+// Uploads `file` to `<remote>/<relative>` with rclone. With `remove_local` the local copy is
+// deleted by rclone once the upload is verified.
+pub fn upload(file: &Path, remote: &str, relative: &Path, remove_local: bool) -> Transfer {
+    let relative: Vec<String> = relative
+        .components()
+        .map(|c| c.as_os_str().to_string_lossy().into_owned())
+        .collect();
+    let destination = format!("{}/{}", remote.trim_end_matches('/'), relative.join("/"));
+    let size = fs::metadata(file).map_or(0, |m| m.len());
+
+    let result = Command::new("rclone")
+        .arg(if remove_local { "moveto" } else { "copyto" })
+        .arg(file)
+        .arg(rclone_remote(&destination))
+        .stdout(Stdio::null())
+        .output()
+        .map_err(|err| format!("could not start rclone: {}", err))
+        .and_then(|output| {
+            if output.status.success() {
+                Ok(())
+            } else {
+                let stderr = String::from_utf8_lossy(&output.stderr);
+                Err(stderr
+                    .lines()
+                    .rev()
+                    .find(|line| !line.trim().is_empty())
+                    .unwrap_or("rclone failed")
+                    .trim()
+                    .to_string())
+            }
+        });
+
+    Transfer {
+        file: file.to_path_buf(),
+        destination,
+        size,
+        result,
+    }
+}
+
+// Remote locations recorded in the history instead of a local path after `--move`.
+pub fn is_remote(path: &Path) -> bool {
+    let path = path.to_string_lossy();
+    path.starts_with("s3://")
+        || path
+            .split_once(':')
+            .is_some_and(|(remote, _)| remote.len() > 1 && !remote.contains(['/', '\\']))
+}
+
+// Destination given as `sftp://user@host[:port]/path`. A path starting with `/~/` is relative
+// to the remote home directory.
+#[derive(Debug, Clone)]
+pub struct SftpTarget {
+    pub host: String,
+    pub port: Option<String>,
+    pub path: String,
+}
+
+impl SftpTarget {
+    pub fn parse(url: &str) -> Option<SftpTarget> {
+        let rest = url.strip_prefix("sftp://")?;
+        let (authority, path) = rest.split_once('/').unwrap_or((rest, ""));
+        let (host, port) = match authority.rsplit_once(':') {
+            Some((host, port)) if port.chars().all(|c| c.is_ascii_digit()) => {
+                (host, Some(port.to_string()))
+            }
+            _ => (authority, None),
+        };
+        if host.is_empty() {
+            return None;
+        }
+        let path = match path.strip_prefix("~/") {
+            Some(relative) => relative.to_string(),
+            None if path == "~" => String::new(),
+            None => format!("/{}", path),
+        };
+        Some(SftpTarget {
+            host: host.to_string(),
+            port,
+            path: path.trim_end_matches('/').to_string(),
+        })
+    }
+
+    fn url(&self, remote_path: &str) -> String {
+        let port = self
+            .port
+            .as_ref()
+            .map_or(String::new(), |p| format!(":{}", p));
+        match remote_path.strip_prefix('/') {
+            Some(absolute) => format!("sftp://{}{}/{}", self.host, port, absolute),
+            None => format!("sftp://{}{}/~/{}", self.host, port, remote_path),
+        }
+    }
+}
+
+// Sends `file` to `<target>/<relative>` over SFTP, resuming a partial remote file when there
+// is one, and deletes the local copy once the remote size matches.
+pub fn sftp_upload(file: &Path, target: &SftpTarget, relative: &Path) -> Transfer {
+    let parts: Vec<String> = relative
+        .components()
+        .map(|c| c.as_os_str().to_string_lossy().into_owned())
+        .collect();
+    let remote_path = if target.path.is_empty() {
+        parts.join("/")
+    } else {
+        format!("{}/{}", target.path, parts.join("/"))
+    };
+    let size = fs::metadata(file).map_or(0, |m| m.len());
+
+    let result = sftp_send(file, target, &remote_path, size).and_then(|()| {
+        fs::remove_file(file).map_err(|err| format!("could not remove local copy: {}", err))
+    });
+
+    Transfer {
+        file: file.to_path_buf(),
+        destination: target.url(&remote_path),
+        size,
+        result,
+    }
+}
+
+fn sftp_send(file: &Path, target: &SftpTarget, remote_path: &str, size: u64) -> Result<(), String> {
+    // Create the remote directories first; `-` makes sftp ignore the ones that already exist
+    let mut mkdirs = String::new();
+    let mut dir = String::new();
+    let components: Vec<&str> = remote_path.split('/').collect();
+    for component in &components[..components.len() - 1] {
+        dir.push_str(component);
+        if !component.is_empty() {
+            mkdirs.push_str(&format!("-mkdir {}\n", sftp_quote(&dir)));
+        }
+        dir.push('/');
+    }
+
+    let local = sftp_quote(&file.to_string_lossy());
+    let remote = sftp_quote(remote_path);
+    // `reput` fails when there is nothing to resume yet, so fall back to a plain upload
+    let resumed = run_sftp(target, &format!("{}reput {} {}\n", mkdirs, local, remote));
+    if resumed.is_err() {
+        run_sftp(target, &format!("{}put {} {}\n", mkdirs, local, remote))?;
+    }
+
+    let listing = run_sftp(target, &format!("ls -ln {}\n", remote))?;
+    let remote_size = listing
+        .lines()
+        .filter_map(|line| line.split_whitespace().nth(4))
+        .find_map(|field| field.parse::<u64>().ok());
+    match remote_size {
+        Some(remote_size) if remote_size == size => Ok(()),
+        Some(remote_size) => Err(format!(
+            "remote file has {} bytes, expected {}",
+            remote_size, size
+        )),
+        None => Err("could not read the size of the remote file".to_string()),
+    }
+}
+
+fn run_sftp(target: &SftpTarget, batch: &str) -> Result<String, String> {
+    let mut command = Command::new("sftp");
+    command.args(["-q", "-b", "-"]);
+    if let Some(port) = &target.port {
+        command.arg("-P").arg(port);
+    }
+    let mut child = command
+        .arg(&target.host)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .map_err(|err| format!("could not start sftp: {}", err))?;
+
+    if let Some(mut stdin) = child.stdin.take() {
+        stdin
+            .write_all(batch.as_bytes())
+            .map_err(|err| format!("could not talk to sftp: {}", err))?;
+    }
+    let output = child
+        .wait_with_output()
+        .map_err(|err| format!("sftp failed: {}", err))?;
+    if output.status.success() {
+        Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+    } else {
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        Err(stderr
+            .lines()
+            .rev()
+            .find(|line| !line.trim().is_empty())
+            .unwrap_or("sftp failed")
+            .trim()
+            .to_string())
+    }
+}
+
+// Quotes a path for an sftp batch file, escaping the characters sftp would glob.
+fn sftp_quote(path: &str) -> String {
+    let mut quoted = String::from("\"");
+    for c in path.chars() {
+        if matches!(c, '"' | '\\' | '*' | '?' | '[' | ']') {
+            quoted.push('\\');
+        }
+        quoted.push(c);
+    }
+    quoted.push('"');
+    quoted
+}
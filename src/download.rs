@@ -0,0 +1,652 @@
+use std::fs;
+use std::io::{self, BufRead, BufReader, IsTerminal, Write};
+use std::path::{Path, PathBuf};
+use std::process::{self, ChildStdout, ExitStatus, Stdio};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::thread;
+
+use crate::aria2;
+use crate::auth;
+use crate::cli::Options;
+use crate::layout::{self, Layout};
+use crate::paths;
+use crate::probe::{Format, Probe};
+use crate::progress::{self, Tracker, Update};
+use crate::twitch;
+use crate::urls::UrlEntry;
+use crate::ytdlp;
+use crate::DOWNLOADING_DIR;
+use crate::{hash, history};
+
+// Names of downloaded files, in the job's output directory.
+pub const OUTPUT_TEMPLATE: &str = "%(title)s.%(ext)s";
+// For a video whose title is already taken by another file, see --on-collision
+pub const OUTPUT_TEMPLATE_WITH_ID: &str = "%(title)s [%(id)s].%(ext)s";
+
+// yt-dlp's exit status once it reached --max-downloads.
+const MAX_DOWNLOADS_REACHED: i32 = 101;
+
+// yt-dlp processes started so far, to name their temporary files.
+static DOWNLOADS: AtomicUsize = AtomicUsize::new(0);
+
+// A URL ready to be handed to yt-dlp.
+pub struct Job {
+    pub entry: UrlEntry,
+    pub output_dir: PathBuf,
+    // Archive key when dlyt could resolve the URL to a single video up front
+    pub canonical_id: Option<String>,
+    // `yt-dlp -J` results, unless probing was skipped or failed
+    pub probe: Option<Probe>,
+    // New files still allowed by --max-downloads
+    pub max_downloads: Option<usize>,
+    // Largest file yt-dlp may start: --max-filesize, or what is left of --data-budget
+    pub max_filesize: Option<u64>,
+    pub output_template: &'static str,
+    pub layout: Layout,
+    // Downloaded alongside other jobs, so without a progress bar
+    pub batched: bool,
+    // With --downloader aria2c, its settings for the URL's host
+    pub aria2c: Option<aria2::Settings>,
+    // Replace an existing file of the same name instead of keeping it
+    pub overwrite: bool,
+}
+
+pub struct Download {
+    pub status: ExitStatus,
+    pub finished: Vec<history::Entry>,
+    // yt-dlp's `ERROR:` lines, for classifying failures
+    pub errors: Vec<String>,
+    // Time spent transferring the finished files, per yt-dlp's progress
+    pub transfer_seconds: f64,
+    // The download crawled along for a minute or more before it ended
+    pub throttled: bool,
+    // yt-dlp stopped because it reached --max-downloads, which is not a failure
+    pub limited: bool,
+}
+
+// How much quality to fetch, from `--quality`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Quality {
+    Best,
+    // Best video up to this height
+    Height(u32),
+    AudioOnly,
+}
+
+impl Quality {
+    pub fn from_name(name: &str) -> Option<Quality> {
+        match name {
+            "best" => Some(Quality::Best),
+            "audio" => Some(Quality::AudioOnly),
+            _ => name.strip_suffix('p')?.parse().ok().map(Quality::Height),
+        }
+    }
+}
+
+// A step of the --fallback chain: a quality, optionally limited to one container, e.g.
+// `1080p-mp4`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Tier {
+    pub name: String,
+    quality: Quality,
+    ext: Option<String>,
+}
+
+impl Tier {
+    pub fn from_name(name: &str) -> Option<Tier> {
+        let (quality, ext) = match name.split_once('-') {
+            Some((_, "")) => return None,
+            Some((quality, ext)) => (quality, Some(ext.to_string())),
+            None => (name, None),
+        };
+        Some(Tier {
+            name: name.to_string(),
+            quality: Quality::from_name(quality)?,
+            ext,
+        })
+    }
+
+    fn format(&self, languages: &[String]) -> String {
+        selector(self.quality, languages, self.ext.as_deref())
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Subtitles {
+    // Automatic captions only
+    Auto,
+    // Uploaded subtitles only
+    Manual,
+    All,
+    None,
+}
+
+impl Subtitles {
+    pub fn from_name(name: &str) -> Option<Subtitles> {
+        match name {
+            "auto" => Some(Subtitles::Auto),
+            "manual" => Some(Subtitles::Manual),
+            "all" => Some(Subtitles::All),
+            "none" => Some(Subtitles::None),
+            _ => None,
+        }
+    }
+}
+
+// Where the description and other metadata go, from `--metadata` or a `metadata=` directive.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Metadata {
+    // A `.description` file next to the video
+    Sidecar,
+    // Tags inside the video file
+    Embed,
+    Both,
+    None,
+}
+
+impl Metadata {
+    pub fn from_name(name: &str) -> Option<Metadata> {
+        match name {
+            "sidecar" => Some(Metadata::Sidecar),
+            "embed" => Some(Metadata::Embed),
+            "both" => Some(Metadata::Both),
+            "none" => Some(Metadata::None),
+            _ => None,
+        }
+    }
+
+    fn args(self) -> &'static [&'static str] {
+        match self {
+            Metadata::Sidecar => &["--write-description"],
+            Metadata::Embed => &["--add-metadata"],
+            Metadata::Both => &["--write-description", "--add-metadata"],
+            Metadata::None => &[],
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Downloader {
+    // yt-dlp's own HTTP downloader
+    Native,
+    // aria2c with several connections per file, for hosts that throttle single connections
+    Aria2c,
+}
+
+impl Downloader {
+    pub fn from_name(name: &str) -> Option<Downloader> {
+        match name {
+            "native" => Some(Downloader::Native),
+            "aria2c" => Some(Downloader::Aria2c),
+            _ => None,
+        }
+    }
+}
+
+// yt-dlp format selector for a quality of a probed URL. When no stream fits under a height
+// limit, the lowest height there is is taken instead of failing the download. Of the preferred
+// audio languages, the first one the video has is asked for.
+pub fn select_format(quality: Quality, languages: &[String], probe: &Probe) -> String {
+    let heights: Vec<u32> = probe
+        .formats
+        .iter()
+        .filter(|format| format.video)
+        .filter_map(|format| format.height)
+        .collect();
+    let quality = match (quality, heights.iter().min()) {
+        (Quality::Height(limit), Some(&lowest)) if lowest > limit => Quality::Height(lowest),
+        _ => quality,
+    };
+
+    let available: Vec<&str> = probe
+        .formats
+        .iter()
+        .filter(|format| format.audio)
+        .filter_map(|format| format.language.as_deref())
+        .collect();
+    if available.is_empty() {
+        return select_format_without_probe(quality, languages);
+    }
+    let language = languages
+        .iter()
+        .find(|language| available.iter().any(|a| is_language(a, language)));
+    select_format_without_probe(
+        quality,
+        language.map(std::slice::from_ref).unwrap_or_default(),
+    )
+}
+
+// Roughly what the format `select_format` picks will download, from the sizes the site
+// reports: the largest stream at the chosen height plus the largest audio stream, or the
+// single file. None when the sizes are unknown.
+pub fn estimated_size(quality: Quality, probe: &Probe) -> Option<u64> {
+    let lowest = probe
+        .formats
+        .iter()
+        .filter(|format| format.video)
+        .filter_map(|format| format.height)
+        .min();
+    let fits = |format: &&Format| match (quality, format.height) {
+        (Quality::Height(limit), Some(height)) => height <= limit.max(lowest.unwrap_or(0)),
+        _ => true,
+    };
+    // Largest of the highest streams that fit
+    let best = |video: bool, audio: bool| {
+        probe
+            .formats
+            .iter()
+            .filter(|format| format.video == video && format.audio == audio)
+            .filter(fits)
+            .max_by_key(|format| (format.height, format.size))
+            .and_then(|format| format.size)
+    };
+
+    let audio = best(false, true);
+    match quality {
+        Quality::AudioOnly => audio.or_else(|| best(true, true)),
+        _ => match (best(true, false), audio) {
+            (Some(video), Some(audio)) => Some(video + audio),
+            _ => best(true, true),
+        },
+    }
+}
+
+// yt-dlp format selector for a quality, trying the audio languages in order, then any audio,
+// then the best single file when there are no separate streams.
+pub fn select_format_without_probe(quality: Quality, languages: &[String]) -> String {
+    selector(quality, languages, None)
+}
+
+// The same, with every stream limited to container `ext`; the audio of an mp4 is an m4a.
+fn selector(quality: Quality, languages: &[String], ext: Option<&str>) -> String {
+    let video_ext = ext.map(|ext| format!("[ext={}]", ext)).unwrap_or_default();
+    let audio_ext = match ext {
+        Some("mp4" | "m4a") => "[ext=m4a]",
+        Some("webm") => "[ext=webm]",
+        _ => "",
+    };
+    let (video, single) = match quality {
+        Quality::Best => (
+            Some(format!("bestvideo{}", video_ext)),
+            format!("best{}", video_ext),
+        ),
+        Quality::Height(height) => (
+            Some(format!("bestvideo[height<={}]{}", height, video_ext)),
+            format!("best[height<={}]{}", height, video_ext),
+        ),
+        Quality::AudioOnly => (None, format!("best{}", video_ext)),
+    };
+
+    let audio = languages
+        .iter()
+        .map(|language| format!("bestaudio[language^={}]{}", language, audio_ext))
+        .chain([format!("bestaudio{}", audio_ext)]);
+    let mut choices: Vec<String> = audio
+        .map(|audio| match &video {
+            Some(video) => format!("{}+{}", video, audio),
+            None => audio,
+        })
+        .collect();
+    choices.extend(
+        languages
+            .iter()
+            .map(|language| format!("{}[language^={}]", single, language)),
+    );
+    choices.push(single);
+    choices.join("/")
+}
+
+// Whether a track's language is a preferred one: `en` matches `en` and `en-US`.
+fn is_language(track: &str, preferred: &str) -> bool {
+    track == preferred
+        || track
+            .strip_prefix(preferred)
+            .is_some_and(|rest| rest.starts_with('-'))
+}
+
+// yt-dlp format sorting for --metered: the `format=` selector or quality policy still decides
+// what is acceptable, but among those the smallest file wins, at no more than 480p when the
+// quality has no height limit of its own.
+fn metered_sort(quality: Quality) -> String {
+    match quality {
+        Quality::Best => "res:480,+size,+br".to_string(),
+        Quality::Height(height) => format!("res:{},+size,+br", height),
+        Quality::AudioOnly => "+size,+br".to_string(),
+    }
+}
+
+// A `format=` directive, then `--format`, both verbatim; otherwise the `--quality` policy.
+fn format_for(opts: &Options, job: &Job) -> String {
+    job.entry
+        .directives
+        .format
+        .clone()
+        .or_else(|| opts.format.clone())
+        .unwrap_or_else(|| match &job.probe {
+            _ if twitch::is_twitch(&job.entry.url) => twitch::format(opts.quality),
+            Some(probe) => select_format(opts.quality, &opts.audio_lang, probe),
+            None => select_format_without_probe(opts.quality, &opts.audio_lang),
+        })
+}
+
+// Videos at least this long get chapters embedded unless told otherwise.
+const LONG_FORM: f64 = 20.0 * 60.0;
+
+// --embed-chapters / --no-embed-chapters, otherwise for Twitch VODs with --twitch-chapters and
+// probed long-form videos.
+fn embed_chapters(opts: &Options, job: &Job) -> bool {
+    opts.embed_chapters.unwrap_or_else(|| {
+        (opts.twitch_chapters && twitch::is_vod(&job.entry.url))
+            || job
+                .probe
+                .as_ref()
+                .and_then(|probe| probe.duration)
+                .is_some_and(|duration| duration >= LONG_FORM)
+    })
+}
+
+fn subtitle_args(subtitles: Subtitles) -> &'static [&'static str] {
+    match subtitles {
+        Subtitles::Auto => &["--write-auto-sub", "--embed-subs"],
+        Subtitles::Manual => &["--write-sub", "--embed-subs"],
+        Subtitles::All => &["--write-sub", "--write-auto-sub", "--embed-subs"],
+        Subtitles::None => &[],
+    }
+}
+
+// Runs yt-dlp for a single job. `authenticated` passes the configured cookies or OAuth
+// tokens (cached in `oauth_dir`) along; a `tier` of the --fallback chain replaces the format.
+// Where yt-dlp notes the files it starts downloading for `url`, one path per line; see
+// `clean::partial_files_of`.
+pub fn names_file(opts: &Options, url: &str) -> PathBuf {
+    opts.state_file(DOWNLOADING_DIR)
+        .join(format!("{}.txt", hash::text_digest(url)))
+}
+
+pub fn download(
+    opts: &Options,
+    job: &Job,
+    archive_file: &Path,
+    oauth_dir: &Path,
+    authenticated: bool,
+    tier: Option<&Tier>,
+) -> io::Result<Download> {
+    // Numbered, as batched jobs download at the same time
+    let number = DOWNLOADS.fetch_add(1, Ordering::Relaxed);
+    // yt-dlp appends a line for every file it finishes to this list
+    let finished_list =
+        std::env::temp_dir().join(format!("dlyt-{}-{}-finished.txt", process::id(), number));
+    // yt-dlp appends to the archive it reads, so with --no-archive-write it reads a copy
+    let archive_copy =
+        std::env::temp_dir().join(format!("dlyt-{}-{}-archive.txt", process::id(), number));
+
+    let vod = twitch::is_vod(&job.entry.url);
+    let chat = vod && opts.twitch_chat;
+
+    let mut command = ytdlp::command();
+    if opts.no_archive {
+        command.arg("--no-download-archive");
+    } else if opts.no_archive_write {
+        match fs::copy(archive_file, &archive_copy) {
+            Ok(_) => {}
+            Err(err) if err.kind() == io::ErrorKind::NotFound => {
+                fs::write(&archive_copy, "")?;
+            }
+            Err(err) => return Err(err),
+        }
+        command.arg("--download-archive").arg(&archive_copy);
+    } else {
+        command.arg("--download-archive").arg(archive_file);
+    }
+    command
+        .arg("-f")
+        .arg(tier.map_or_else(
+            || format_for(opts, job),
+            |tier| tier.format(&opts.audio_lang),
+        ))
+        .arg("--prefer-ffmpeg")
+        .args(
+            match job.entry.directives.metadata.unwrap_or(opts.metadata) {
+                // Short-form videos get tags but no sidecars
+                Metadata::Sidecar if job.layout == Layout::Shorts => Metadata::None,
+                Metadata::Both if job.layout == Layout::Shorts => Metadata::Embed,
+                metadata => metadata,
+            }
+            .args(),
+        )
+        .args(subtitle_args(match opts.subtitles {
+            _ if job.layout == Layout::Shorts => Subtitles::None,
+            // VODs have no subtitles; the chat replay is fetched as one, but can't be embedded
+            _ if chat => Subtitles::None,
+            // Burning subtitles in needs them embedded first
+            Subtitles::None if opts.burn_subs.is_some() => Subtitles::All,
+            subtitles => subtitles,
+        }))
+        // --add-metadata embeds chapters too unless told not to
+        .arg(if embed_chapters(opts, job) {
+            "--embed-chapters"
+        } else {
+            "--no-embed-chapters"
+        });
+    if opts.metered {
+        command.arg("-S").arg(metered_sort(opts.quality));
+    }
+    if let Some(max) = job.max_filesize {
+        command.arg("--max-filesize").arg(max.to_string());
+    }
+    if chat {
+        command.arg("--write-subs").arg("--sub-langs").arg("rechat");
+    } else if let Some(language) = &opts.burn_subs {
+        command.arg("--sub-langs").arg(format!("{}.*", language));
+    }
+    if opts.thumbnail {
+        command.arg("--write-thumbnail").arg("--embed-thumbnail");
+    }
+    if let Some(fragments) = opts.concurrent_fragments {
+        command
+            .arg("--concurrent-fragments")
+            .arg(fragments.to_string());
+    }
+    // Without a .part file an interrupted download starts over. yt-dlp continues from one by
+    // default, so --discard-partial has to say otherwise for a file the cleanup missed.
+    if opts.no_part {
+        command.arg("--no-part");
+    }
+    if opts.discard_partial {
+        command.arg("--no-continue");
+    } else if !opts.no_part {
+        command.arg("--continue");
+    }
+    if let Some(settings) = job.aria2c {
+        command
+            .arg("--downloader")
+            .arg("aria2c")
+            .arg("--downloader-args")
+            .arg(settings.downloader_args(!opts.discard_partial));
+    }
+    // Written as a download starts, so the next run finds its partial files after a crash
+    let names = names_file(opts, &job.entry.url);
+    if let Some(dir) = names.parent() {
+        fs::create_dir_all(dir)?;
+    }
+    command
+        .arg("--print-to-file")
+        .arg("before_dl:%(filename)s")
+        .arg(&names);
+    if vod {
+        command.args(twitch::vod_args(
+            &job.output_dir.join(job.output_template),
+            opts.twitch_chapters,
+            // Unless set, or aria2c fetches the segments
+            opts.concurrent_fragments.is_none() && job.aria2c.is_none(),
+        ));
+    }
+    command
+        .arg("-o")
+        .arg(job.output_dir.join(job.output_template))
+        .arg("--print-to-file")
+        .arg(history::PRINT_TEMPLATE)
+        .arg(&finished_list);
+    if job.layout == Layout::Music {
+        command.args(layout::MUSIC_ARGS).arg("-o").arg(format!(
+            "thumbnail:{}",
+            job.output_dir.join(layout::MUSIC_COVER_TEMPLATE).display()
+        ));
+    }
+    // Windows refuses longer paths unless they are enabled in the registry
+    if cfg!(target_os = "windows") {
+        command
+            .arg("--trim-filenames")
+            .arg(paths::name_limit(&job.output_dir).to_string());
+    }
+    if job.overwrite {
+        command.arg("--force-overwrites");
+    }
+    // Keep yt-dlp from expanding a `list=` parameter once the URL resolved to one video
+    if job.canonical_id.is_some() {
+        command.arg("--no-playlist");
+    }
+    if let Some(items) = &job.entry.directives.items {
+        command.arg("--playlist-items").arg(items);
+    }
+    // A playlist handed over whole (--skip-probe) must not overshoot the limit either
+    if let Some(max) = job.max_downloads {
+        command.arg("--max-downloads").arg(max.to_string());
+    }
+    if let Some(proxy) = &opts.proxy {
+        command.arg("--proxy").arg(proxy);
+    }
+    if authenticated {
+        if let Some(cookies) = &opts.cookies {
+            command.arg("--cookies").arg(cookies);
+        }
+        if let Some(browser) = &opts.cookies_from_browser {
+            command.arg("--cookies-from-browser").arg(browser);
+        }
+        if opts.oauth {
+            command.args(auth::oauth_args(oauth_dir));
+        }
+    }
+
+    let mut child = command
+        // One line per progress update, in a form dlyt parses, instead of a redrawn one
+        .arg("--newline")
+        .arg("--progress-template")
+        .arg(progress::TEMPLATE)
+        .arg("--")
+        .arg(&job.entry.url)
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()?;
+
+    let stdout = child.stdout.take();
+    let (errors, tracker) = thread::scope(|scope| -> io::Result<(Vec<String>, Tracker)> {
+        let progress = stdout.map(|stdout| scope.spawn(move || show_progress(opts, job, stdout)));
+
+        // Pass yt-dlp's messages through while keeping the errors
+        let mut errors = Vec::new();
+        if let Some(stderr) = child.stderr.take() {
+            for line in BufReader::new(stderr).split(b'\n') {
+                let line = String::from_utf8_lossy(&line?).trim_end().to_string();
+                eprintln!("{}", line);
+                if line.starts_with("ERROR:") {
+                    errors.push(line);
+                }
+            }
+        }
+        let tracker = progress
+            .and_then(|progress| progress.join().ok())
+            .unwrap_or_default();
+        Ok((errors, tracker))
+    })?;
+    let status = child.wait()?;
+
+    let finished = fs::read_to_string(&finished_list).unwrap_or_default();
+    let _ = fs::remove_file(&finished_list);
+    if opts.no_archive_write {
+        let _ = fs::remove_file(&archive_copy);
+    }
+    let finished: Vec<history::Entry> = finished
+        .lines()
+        .filter_map(history::Entry::from_ytdlp)
+        .map(|entry| history::Entry {
+            connections: job.aria2c.map(|settings| settings.connections),
+            ..entry
+        })
+        .collect();
+    if vod && opts.twitch_chapters {
+        for entry in &finished {
+            twitch::write_chapters(&entry.path);
+        }
+    }
+
+    Ok(Download {
+        status,
+        finished,
+        errors,
+        transfer_seconds: tracker.transfer_seconds,
+        throttled: tracker.throttled(),
+        limited: job.max_downloads.is_some() && status.code() == Some(MAX_DOWNLOADS_REACHED),
+    })
+}
+
+// Turns yt-dlp's progress lines into a progress bar, or events with --progress-json, and
+// passes its other output on. With --progress-json or --porcelain that goes to stderr, to keep
+// stdout to events and result lines.
+fn show_progress(opts: &Options, job: &Job, stdout: ChildStdout) -> Tracker {
+    let mut tracker = Tracker::default();
+    let machine = opts.progress_json || opts.porcelain;
+    // The bars of downloads running side by side would draw over each other
+    let terminal = !job.batched && io::stdout().is_terminal();
+    // A bar is drawn on the current line and needs ending before anything else is printed
+    let mut drawn = false;
+    let mut drawn_len = 0;
+    for line in BufReader::new(stdout).split(b'\n') {
+        let Ok(line) = line else { break };
+        let line = String::from_utf8_lossy(&line).trim_end().to_string();
+        let Some(update) = Update::parse(&line) else {
+            if machine {
+                eprintln!("{}", line);
+            } else {
+                if drawn {
+                    println!();
+                    drawn = false;
+                }
+                println!("{}", line);
+            }
+            continue;
+        };
+
+        tracker.update(&update);
+        let finished = update.status == "finished";
+        if opts.progress_json {
+            progress::Event {
+                phase: "download",
+                url: &job.entry.url,
+                id: job.canonical_id.as_deref(),
+                percent: update.percent(),
+                speed: update.speed,
+                eta: update.eta,
+                ..Default::default()
+            }
+            .emit();
+        } else if !opts.porcelain && terminal {
+            // Padded to cover a longer line drawn before
+            let display = update.display();
+            print!("\r{:<1$}", display, drawn_len);
+            drawn_len = display.len();
+            let _ = io::stdout().flush();
+            drawn = !finished;
+            if finished {
+                println!();
+                drawn_len = 0;
+            }
+        } else if !opts.porcelain && finished {
+            println!("{}", update.display());
+        }
+    }
+    if drawn {
+        println!();
+    }
+    tracker
+}
@@ -0,0 +1,160 @@
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use crate::clean::files_below;
+use crate::json;
+use crate::units::human_size;
+
+// Largest files listed per collection.
+const LARGEST: usize = 3;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SortBy {
+    Size,
+    Files,
+    Average,
+    Name,
+}
+
+impl SortBy {
+    pub fn from_name(name: &str) -> Option<SortBy> {
+        match name {
+            "size" => Some(SortBy::Size),
+            "files" => Some(SortBy::Files),
+            "average" => Some(SortBy::Average),
+            "name" => Some(SortBy::Name),
+            _ => None,
+        }
+    }
+}
+
+// Storage used by one `.urls` file's subdirectory; `default` is the files directly in the
+// output directory.
+struct Collection {
+    name: String,
+    path: PathBuf,
+    files: Vec<(PathBuf, u64)>,
+}
+
+impl Collection {
+    fn bytes(&self) -> u64 {
+        self.files.iter().map(|(_, size)| size).sum()
+    }
+
+    fn average(&self) -> u64 {
+        self.bytes()
+            .checked_div(self.files.len() as u64)
+            .unwrap_or(0)
+    }
+}
+
+pub fn show(output: &Path, sort: SortBy, as_json: bool) -> io::Result<()> {
+    let mut collections = collect(output)?;
+    match sort {
+        SortBy::Size => collections.sort_by_key(|c| std::cmp::Reverse(c.bytes())),
+        SortBy::Files => collections.sort_by_key(|c| std::cmp::Reverse(c.files.len())),
+        SortBy::Average => collections.sort_by_key(|c| std::cmp::Reverse(c.average())),
+        SortBy::Name => collections.sort_by(|a, b| a.name.cmp(&b.name)),
+    }
+
+    if as_json {
+        print_json(&collections);
+        return Ok(());
+    }
+    if collections.is_empty() {
+        println!("Nothing downloaded yet in {}.", output.display());
+        return Ok(());
+    }
+
+    println!(
+        "{:<24} {:>7} {:>11} {:>11}",
+        "COLLECTION", "FILES", "SIZE", "AVERAGE"
+    );
+    for collection in &collections {
+        println!(
+            "{:<24} {:>7} {:>11} {:>11}",
+            collection.name,
+            collection.files.len(),
+            human_size(collection.bytes()),
+            human_size(collection.average())
+        );
+        for (path, size) in collection.files.iter().take(LARGEST) {
+            println!("    {:>10}  {}", human_size(*size), path.display());
+        }
+    }
+    let files: usize = collections.iter().map(|c| c.files.len()).sum();
+    let bytes: u64 = collections.iter().map(Collection::bytes).sum();
+    println!(
+        "Total: {} files, {} in {}.",
+        files,
+        human_size(bytes),
+        output.display()
+    );
+    Ok(())
+}
+
+// Groups every file below `output` by its top-level subdirectory, largest files first.
+fn collect(output: &Path) -> io::Result<Vec<Collection>> {
+    let mut collections: Vec<Collection> = Vec::new();
+    for file in files_below(output)? {
+        let relative = file.strip_prefix(output).unwrap_or(&file);
+        let mut components = relative.components();
+        let first = components.next();
+        let (name, path) = match (first, components.next()) {
+            (Some(dir), Some(_)) => {
+                let name = dir.as_os_str().to_string_lossy().to_string();
+                let path = output.join(&name);
+                (name, path)
+            }
+            _ => ("default".to_string(), output.to_path_buf()),
+        };
+        let size = fs::metadata(&file).map_or(0, |m| m.len());
+
+        match collections.iter_mut().find(|c| c.name == name) {
+            Some(collection) => collection.files.push((file, size)),
+            None => collections.push(Collection {
+                name,
+                path,
+                files: vec![(file, size)],
+            }),
+        }
+    }
+
+    for collection in &mut collections {
+        collection
+            .files
+            .sort_by_key(|(_, size)| std::cmp::Reverse(*size));
+    }
+    Ok(collections)
+}
+
+fn print_json(collections: &[Collection]) {
+    let items: Vec<String> = collections
+        .iter()
+        .map(|c| {
+            let largest: Vec<String> = c
+                .files
+                .iter()
+                .take(LARGEST)
+                .map(|(path, size)| {
+                    format!(
+                        "{{\"path\":{},\"bytes\":{}}}",
+                        json::quote(&path.to_string_lossy()),
+                        size
+                    )
+                })
+                .collect();
+            format!(
+                "{{\"name\":{},\"path\":{},\"files\":{},\"bytes\":{},\"average\":{},\"largest\":[{}]}}",
+                json::quote(&c.name),
+                json::quote(&c.path.to_string_lossy()),
+                c.files.len(),
+                c.bytes(),
+                c.average(),
+                largest.join(",")
+            )
+        })
+        .collect();
+    println!("{{\"collections\":[{}]}}", items.join(","));
+}
@@ -0,0 +1,98 @@
+use std::collections::{BTreeMap, HashSet};
+use std::io;
+use std::path::{Path, PathBuf};
+
+use crate::units::human_size;
+use crate::{deliver, hash, history};
+
+// What a run does with a new file whose contents match one already downloaded (a re-upload,
+// or a mirror channel), from `--on-duplicate`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OnDuplicate {
+    // Keep it and say so
+    Warn,
+    // Delete it with its sidecars before it is delivered or recorded
+    Skip,
+}
+
+impl OnDuplicate {
+    pub fn from_name(name: &str) -> Option<OnDuplicate> {
+        match name {
+            "warn" => Some(OnDuplicate::Warn),
+            "skip" => Some(OnDuplicate::Skip),
+            _ => None,
+        }
+    }
+}
+
+// Lists the recorded files with identical contents. Files recorded before hashing was turned
+// on are hashed now, and their digests kept in the history.
+pub fn show(history_file: &Path) -> io::Result<()> {
+    let mut entries = history::load(history_file)?;
+    let mut hashed = 0;
+    let mut seen = HashSet::new();
+    let mut groups: BTreeMap<(u64, String), Vec<usize>> = BTreeMap::new();
+    for (index, entry) in entries.iter_mut().enumerate() {
+        // Files redownloaded to the same path are recorded more than once
+        if deliver::is_remote(&entry.path) || !seen.insert(entry.path.clone()) {
+            continue;
+        }
+        if !entry.path.is_file() {
+            continue;
+        }
+        if entry.hash.is_none() {
+            match hash::file_digest(&entry.path) {
+                Ok(digest) => {
+                    entry.hash = Some(digest);
+                    hashed += 1;
+                }
+                Err(err) => {
+                    println!("Could not read {}: {}", entry.path.display(), err);
+                    continue;
+                }
+            }
+        }
+        if let Some(digest) = &entry.hash {
+            groups
+                .entry((entry.size, digest.clone()))
+                .or_default()
+                .push(index);
+        }
+    }
+    if hashed > 0 {
+        history::save(history_file, &entries)?;
+    }
+
+    let mut duplicates = 0;
+    let mut wasted = 0;
+    for ((size, _), indices) in groups.iter().filter(|(_, indices)| indices.len() > 1) {
+        println!("{} copies of {}:", indices.len(), human_size(*size));
+        for &index in indices {
+            let entry = &entries[index];
+            println!("    {}  ({})", entry.path.display(), entry.url);
+        }
+        duplicates += indices.len() - 1;
+        wasted += size * (indices.len() as u64 - 1);
+    }
+    if duplicates == 0 {
+        println!("No duplicates among the recorded files.");
+    } else {
+        println!(
+            "{} duplicate file(s); deleting them would free {}.",
+            duplicates,
+            human_size(wasted)
+        );
+    }
+    Ok(())
+}
+
+// Paths and archive keys of the recorded files by size and digest, to spot new files that are
+// already in the library.
+pub fn known_digests(
+    history_file: &Path,
+) -> io::Result<BTreeMap<(u64, String), (PathBuf, String)>> {
+    Ok(history::load(history_file)?
+        .into_iter()
+        .filter_map(|entry| Some(((entry.size, entry.hash?), (entry.path, entry.archive_id))))
+        .collect())
+}
@@ -0,0 +1,120 @@
+use std::io::{self, Write};
+use std::path::Path;
+
+use crate::history::{self, Entry};
+use crate::{date, json};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ExportFormat {
+    Csv,
+    Json,
+}
+
+impl ExportFormat {
+    pub fn from_name(name: &str) -> Option<ExportFormat> {
+        match name {
+            "csv" => Some(ExportFormat::Csv),
+            "json" => Some(ExportFormat::Json),
+            _ => None,
+        }
+    }
+}
+
+const FIELDS: &[&str] = &[
+    "timestamp",
+    "archive_id",
+    "title",
+    "path",
+    "format",
+    "size",
+    "url",
+    "uploader",
+    "seconds",
+    "hash",
+    "connections",
+    "ytdlp_version",
+];
+
+// Writes every history entry recorded at or after `since` (seconds since the epoch) to stdout.
+pub fn export(history_file: &Path, format: ExportFormat, since: Option<u64>) -> io::Result<()> {
+    let entries: Vec<Entry> = history::load(history_file)?
+        .into_iter()
+        .filter(|entry| {
+            since.is_none_or(|since| {
+                date::parse_timestamp(&entry.timestamp).is_some_and(|secs| secs >= since)
+            })
+        })
+        .collect();
+
+    let mut out = io::stdout().lock();
+    match format {
+        ExportFormat::Csv => {
+            writeln!(out, "{}", FIELDS.join(","))?;
+            for entry in &entries {
+                let row: Vec<String> = fields(entry).iter().map(|f| csv_field(f)).collect();
+                writeln!(out, "{}", row.join(","))?;
+            }
+        }
+        ExportFormat::Json => write_json(&mut out, &entries)?,
+    }
+    Ok(())
+}
+
+// `dlyt history --json`: the last `limit` entries, for scripts and PowerShell's
+// `ConvertFrom-Json`.
+pub fn recent(history_file: &Path, limit: usize) -> io::Result<()> {
+    let entries = history::load(history_file)?;
+    let skip = entries.len().saturating_sub(limit);
+    write_json(&mut io::stdout().lock(), &entries[skip..])
+}
+
+fn write_json(out: &mut impl Write, entries: &[Entry]) -> io::Result<()> {
+    writeln!(out, "[")?;
+    for (i, entry) in entries.iter().enumerate() {
+        let values = fields(entry);
+        let members: Vec<String> = FIELDS
+            .iter()
+            .zip(&values)
+            .map(|(name, value)| match *name {
+                "size" | "seconds" => format!("\"{}\":{}", name, value),
+                "hash" | "connections" | "ytdlp_version" if value.is_empty() => {
+                    format!("\"{}\":null", name)
+                }
+                "connections" => format!("\"{}\":{}", name, value),
+                _ => format!("\"{}\":{}", name, json::quote(value)),
+            })
+            .collect();
+        let separator = if i + 1 < entries.len() { "," } else { "" };
+        writeln!(out, "  {{{}}}{}", members.join(","), separator)?;
+    }
+    writeln!(out, "]")
+}
+
+// An entry's values in `FIELDS` order.
+fn fields(entry: &Entry) -> Vec<String> {
+    vec![
+        entry.timestamp.clone(),
+        entry.archive_id.clone(),
+        entry.title.clone(),
+        entry.path.to_string_lossy().to_string(),
+        entry.format.clone(),
+        entry.size.to_string(),
+        entry.url.clone(),
+        entry.uploader.clone(),
+        format!("{:.1}", entry.seconds),
+        entry.hash.clone().unwrap_or_default(),
+        entry
+            .connections
+            .map(|connections| connections.to_string())
+            .unwrap_or_default(),
+        entry.ytdlp_version.clone().unwrap_or_default(),
+    ]
+}
+
+fn csv_field(value: &str) -> String {
+    if value.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        value.to_string()
+    }
+}
@@ -0,0 +1,395 @@
+use std::collections::{BTreeMap, HashSet};
+use std::fs::{self, OpenOptions};
+use std::io::{self, Write};
+use std::path::Path;
+
+use crate::date;
+use crate::units;
+use crate::urls::UrlEntry;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum ErrorClass {
+    AgeRestricted,
+    // Not available in this country; a proxy elsewhere may help
+    GeoBlocked,
+    Drm,
+    // Needs an account, for no reason given more specifically below
+    LoginRequired,
+    MembersOnly,
+    // Needs a paid subscription, such as YouTube Premium
+    Premium,
+    Private,
+    // A premiere or live stream that has not started yet
+    Upcoming,
+    Removed,
+    Unsupported,
+    // The chosen format could not be downloaded, though others might
+    Format,
+    Throttled,
+    Network,
+    // yt-dlp could not make sense of the site, usually fixed by updating it
+    Extractor,
+    Unknown,
+}
+
+impl ErrorClass {
+    pub const ALL: &'static [ErrorClass] = &[
+        ErrorClass::AgeRestricted,
+        ErrorClass::GeoBlocked,
+        ErrorClass::Drm,
+        ErrorClass::LoginRequired,
+        ErrorClass::MembersOnly,
+        ErrorClass::Premium,
+        ErrorClass::Private,
+        ErrorClass::Upcoming,
+        ErrorClass::Removed,
+        ErrorClass::Unsupported,
+        ErrorClass::Format,
+        ErrorClass::Throttled,
+        ErrorClass::Network,
+        ErrorClass::Extractor,
+        ErrorClass::Unknown,
+    ];
+
+    pub fn label(self) -> &'static str {
+        match self {
+            ErrorClass::AgeRestricted => "age-restricted",
+            ErrorClass::GeoBlocked => "geo-blocked",
+            ErrorClass::Drm => "drm",
+            ErrorClass::LoginRequired => "login-required",
+            ErrorClass::MembersOnly => "members-only",
+            ErrorClass::Premium => "premium",
+            ErrorClass::Private => "private",
+            ErrorClass::Upcoming => "upcoming",
+            ErrorClass::Removed => "removed",
+            ErrorClass::Unsupported => "unsupported",
+            ErrorClass::Format => "format",
+            ErrorClass::Throttled => "throttled",
+            ErrorClass::Network => "network",
+            ErrorClass::Extractor => "extractor-broken",
+            ErrorClass::Unknown => "unknown",
+        }
+    }
+
+    pub fn from_label(label: &str) -> Option<ErrorClass> {
+        ErrorClass::ALL.iter().copied().find(|c| c.label() == label)
+    }
+
+    // What might get past a failure of this class, for the run summary
+    pub fn hint(self) -> Option<&'static str> {
+        match self {
+            ErrorClass::AgeRestricted
+            | ErrorClass::LoginRequired
+            | ErrorClass::MembersOnly
+            | ErrorClass::Premium
+            | ErrorClass::Private => Some("--cookies or --oauth with an account that has access"),
+            ErrorClass::GeoBlocked => Some("--proxy in a country the videos are available in"),
+            ErrorClass::Drm => Some("none, DRM-protected videos cannot be downloaded"),
+            ErrorClass::Throttled => Some("waiting, or fewer downloads per run"),
+            ErrorClass::Extractor => Some("updating yt-dlp"),
+            _ => None,
+        }
+    }
+
+    // Failures an account can get past, by cookies or OAuth
+    pub fn needs_auth(self) -> bool {
+        matches!(
+            self,
+            ErrorClass::AgeRestricted
+                | ErrorClass::LoginRequired
+                | ErrorClass::MembersOnly
+                | ErrorClass::Premium
+                | ErrorClass::Private
+        )
+    }
+
+    // Videos that cannot be downloaded as they are, however often they are retried
+    pub fn unavailable(self) -> bool {
+        matches!(
+            self,
+            ErrorClass::MembersOnly
+                | ErrorClass::Premium
+                | ErrorClass::Private
+                | ErrorClass::Upcoming
+                | ErrorClass::Removed
+        )
+    }
+}
+
+// Sorts yt-dlp's `ERROR:` lines into a class, checking the most specific patterns first.
+pub fn classify(errors: &[String]) -> ErrorClass {
+    let text = errors.join("\n").to_lowercase();
+    let matches = |patterns: &[&str]| patterns.iter().any(|p| text.contains(p));
+
+    if matches(&[
+        "confirm your age",
+        "age-restricted",
+        "age restricted",
+        "inappropriate for some users",
+    ]) {
+        ErrorClass::AgeRestricted
+    } else if matches(&[
+        "not available in your country",
+        "not made this video available in your country",
+        "blocked it in your country",
+        "not available in your location",
+        "not available from your location",
+        "geo restriction",
+        "geo-restrict",
+        "geo restricted",
+    ]) {
+        ErrorClass::GeoBlocked
+    } else if matches(&["drm protected", "known to use drm", "widevine"]) {
+        ErrorClass::Drm
+    } else if matches(&[
+        "members-only",
+        "members only",
+        "join this channel",
+        "available to this channel's members",
+        "only available for subscribers",
+        "only available to subscribers",
+        "subscriber-only",
+        "subscribers only",
+        "subscribers-only",
+    ]) {
+        ErrorClass::MembersOnly
+    } else if matches(&[
+        "premium members",
+        "youtube premium",
+        "requires a premium",
+        "premium subscription",
+        "premium account",
+    ]) {
+        ErrorClass::Premium
+    } else if matches(&["private video", "this video is private"]) {
+        ErrorClass::Private
+    } else if matches(&[
+        "premieres in",
+        "premiere will begin",
+        "live event will begin",
+        "this live event has not started",
+        "is upcoming",
+        "scheduled to start",
+    ]) {
+        ErrorClass::Upcoming
+    } else if matches(&[
+        "login required",
+        "requires authentication",
+        "you need to log in",
+        "sign in to view",
+        "account credentials",
+        "this video requires login",
+    ]) {
+        ErrorClass::LoginRequired
+    } else if matches(&[
+        // YouTube's rate limit, which also starts with "Video unavailable"
+        "try again later",
+        "content isn't available",
+        "content isn’t available",
+    ]) {
+        ErrorClass::Throttled
+    } else if matches(&[
+        "has been removed",
+        "has been terminated",
+        "no longer available",
+        "does not exist",
+        "http error 404",
+    ]) {
+        ErrorClass::Removed
+    } else if matches(&["unsupported url", "no suitable extractor"]) {
+        ErrorClass::Unsupported
+    } else if matches(&[
+        "requested format is not available",
+        "requested format not available",
+        "unable to download video data: http error 403",
+    ]) || (text.contains("fragment")
+        && matches(&["http error 403", "not found", "giving up after"]))
+    {
+        ErrorClass::Format
+    } else if matches(&[
+        "http error 403",
+        "http error 429",
+        "too many requests",
+        "confirm you're not a bot",
+        "confirm you’re not a bot",
+        "rate-limit",
+        "rate limit",
+    ]) {
+        ErrorClass::Throttled
+    } else if matches(&[
+        "unable to download webpage",
+        "timed out",
+        "connection reset",
+        "connection refused",
+        "network is unreachable",
+        "name resolution",
+        "getaddrinfo failed",
+        "urlopen error",
+        "incompleteread",
+    ]) {
+        ErrorClass::Network
+    } else if matches(&[
+        "unable to extract",
+        "unable to parse",
+        "please report this issue",
+        "signature extraction failed",
+        "nsig extraction failed",
+        "keyerror",
+    ]) {
+        ErrorClass::Extractor
+    } else {
+        ErrorClass::Unknown
+    }
+}
+
+// What to do with a URL once its immediate retries are used up.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Action {
+    // Try again on the next run
+    Keep,
+    // Also re-attempt it between runs in daemon mode, with backoff
+    Requeue,
+    // Skip it in later runs; it is listed in dropped.tsv until removed from there
+    Drop,
+    // Comment out its line in the .urls file, with the date and reason
+    Annotate,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct Policy {
+    // Immediate retries within the same run
+    pub retries: u32,
+    // Wait before each immediate retry, multiplied by the attempt number
+    pub delay: u64,
+    pub action: Action,
+}
+
+impl Policy {
+    pub fn default_for(class: ErrorClass) -> Policy {
+        let (retries, action) = match class {
+            ErrorClass::Network => (2, Action::Requeue),
+            ErrorClass::Throttled | ErrorClass::Unknown => (0, Action::Requeue),
+            _ => (0, Action::Keep),
+        };
+        Policy {
+            retries,
+            delay: 10,
+            action,
+        }
+    }
+
+    fn set(&mut self, key: &str, value: &str) -> Result<(), String> {
+        match key {
+            "retries" => {
+                self.retries = value
+                    .parse()
+                    .map_err(|_| format!("invalid number of retries: {}", value))?
+            }
+            "delay" => {
+                self.delay = units::parse_duration(value)
+                    .ok_or_else(|| format!("invalid delay: {}", value))?
+            }
+            "action" => {
+                self.action = match value {
+                    "keep" => Action::Keep,
+                    "requeue" => Action::Requeue,
+                    "drop" => Action::Drop,
+                    "annotate" => Action::Annotate,
+                    _ => {
+                        return Err(format!(
+                            "unknown action `{}` (expected keep, requeue, drop or annotate)",
+                            value
+                        ))
+                    }
+                }
+            }
+            _ => return Err(format!("unknown failure policy setting `{}`", key)),
+        }
+        Ok(())
+    }
+}
+
+// Failure policies configured in `[on-error.<class>]` sections, falling back to the defaults.
+#[derive(Debug, Clone, Default)]
+pub struct Policies(BTreeMap<ErrorClass, Policy>);
+
+impl Policies {
+    pub fn get(&self, class: ErrorClass) -> Policy {
+        self.0
+            .get(&class)
+            .copied()
+            .unwrap_or_else(|| Policy::default_for(class))
+    }
+
+    pub fn set(&mut self, class: &str, key: &str, value: &str) -> Result<(), String> {
+        let class = ErrorClass::from_label(class).ok_or_else(|| {
+            let known: Vec<&str> = ErrorClass::ALL.iter().map(|c| c.label()).collect();
+            format!(
+                "unknown error class `{}` (expected one of {})",
+                class,
+                known.join(", ")
+            )
+        })?;
+        self.0
+            .entry(class)
+            .or_insert_with(|| Policy::default_for(class))
+            .set(key, value)
+    }
+}
+
+// URLs dropped by a failure policy, skipped by later runs.
+pub fn load_dropped(file: &Path) -> HashSet<String> {
+    fs::read_to_string(file)
+        .unwrap_or_default()
+        .lines()
+        .filter_map(|line| line.split('\t').next())
+        .map(str::to_string)
+        .collect()
+}
+
+pub fn record_dropped(
+    file: &Path,
+    entry: &UrlEntry,
+    class: ErrorClass,
+    message: &str,
+) -> io::Result<()> {
+    let mut out = OpenOptions::new().create(true).append(true).open(file)?;
+    writeln!(
+        out,
+        "{}\t{}\t{}\t{}",
+        entry.url,
+        class.label(),
+        date::format_timestamp(date::now()),
+        message.replace(['\t', '\n'], " ")
+    )
+}
+
+// Appends URLs that need an authenticated pass to `file`, skipping ones already listed. The
+// file uses the .urls format so it can be moved into the urls directory once cookies are set up.
+pub fn record_needs_auth(file: &Path, entries: &[UrlEntry]) -> io::Result<()> {
+    let existing = fs::read_to_string(file).unwrap_or_default();
+    let mut listed: HashSet<&str> = existing
+        .lines()
+        .filter(|line| !line.starts_with('#'))
+        .filter_map(|line| line.split('|').next())
+        .map(str::trim)
+        .collect();
+
+    let mut out = OpenOptions::new().create(true).append(true).open(file)?;
+    if existing.is_empty() {
+        writeln!(out, "# URLs that need an account to download (age-restricted, members-only or private). Retry them with --cookies, --cookies-from-browser or --oauth.")?;
+    }
+    for entry in entries {
+        if listed.insert(entry.url.as_str()) {
+            writeln!(
+                out,
+                "# {}:{}, {}",
+                entry.file.display(),
+                entry.line,
+                date::format_timestamp(date::now())
+            )?;
+            writeln!(out, "{}", entry.url)?;
+        }
+    }
+    Ok(())
+}
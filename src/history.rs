@@ -0,0 +1,292 @@
+use std::collections::HashSet;
+use std::fs::{self, OpenOptions};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+
+use crate::failure::ErrorClass;
+use crate::units::human_size;
+use crate::{date, deliver};
+
+// One finished download. `archive_id` is the same `<extractor> <id>` key yt-dlp writes to
+// the download archive, so the two files can be joined.
+#[derive(Debug, Clone, Default)]
+pub struct Entry {
+    pub timestamp: String,
+    pub archive_id: String,
+    pub title: String,
+    pub path: PathBuf,
+    pub format: String,
+    pub size: u64,
+    pub url: String,
+    pub uploader: String,
+    // Share of the yt-dlp run's wall time spent on this file, for download speed statistics
+    pub seconds: f64,
+    // xxHash64 of the contents, with --hash or once `dlyt dupes` saw the file
+    pub hash: Option<String>,
+    // aria2c connections it was downloaded with, for tuning them per host
+    pub connections: Option<u32>,
+    // `yt-dlp --version` of the run that downloaded it, to tell which update broke something
+    pub ytdlp_version: Option<String>,
+    // Thumbnail URL for notifications; not kept in the history file
+    pub thumbnail: String,
+    // Length in seconds when yt-dlp knew it, for the index.json manifests; not kept either
+    pub duration: Option<f64>,
+    // YYYY-MM-DD, for the index.json manifests and playlists; not kept either
+    pub upload_date: Option<String>,
+}
+
+impl Entry {
+    // Parses the tab-separated line yt-dlp prints for a finished file, see `PRINT_TEMPLATE`.
+    pub fn from_ytdlp(line: &str) -> Option<Entry> {
+        let mut fields = line.splitn(10, '\t');
+        let extractor = fields.next()?;
+        let id = fields.next()?;
+        let title = fields.next()?;
+        let format = fields.next()?;
+        let url = fields.next()?;
+        let uploader = fields.next()?;
+        let thumbnail = fields.next()?;
+        // "NA" when unknown
+        let duration = fields.next()?.parse().ok();
+        let upload_date = fields.next()?;
+        let upload_date = (upload_date.len() == 8
+            && upload_date.bytes().all(|b| b.is_ascii_digit()))
+        .then(|| {
+            format!(
+                "{}-{}-{}",
+                &upload_date[..4],
+                &upload_date[4..6],
+                &upload_date[6..]
+            )
+        });
+        let path = PathBuf::from(fields.next()?);
+        let size = fs::metadata(&path).map(|m| m.len()).unwrap_or(0);
+
+        Some(Entry {
+            timestamp: date::format_timestamp(date::now()),
+            archive_id: format!("{} {}", extractor.to_lowercase(), id),
+            title: title.to_string(),
+            path,
+            format: format.to_string(),
+            size,
+            url: url.to_string(),
+            uploader: uploader.to_string(),
+            seconds: 0.0,
+            hash: None,
+            connections: None,
+            ytdlp_version: None,
+            thumbnail: thumbnail.to_string(),
+            duration,
+            upload_date,
+        })
+    }
+
+    fn to_line(&self) -> String {
+        [
+            self.timestamp.as_str(),
+            &self.archive_id,
+            &self.title,
+            &self.path.to_string_lossy(),
+            &self.format,
+            &self.size.to_string(),
+            &self.url,
+            &self.uploader,
+            &format!("{:.1}", self.seconds),
+            self.hash.as_deref().unwrap_or_default(),
+            &self
+                .connections
+                .map(|connections| connections.to_string())
+                .unwrap_or_default(),
+            self.ytdlp_version.as_deref().unwrap_or_default(),
+        ]
+        .iter()
+        .map(|field| field.replace(['\t', '\n', '\r'], " "))
+        .collect::<Vec<_>>()
+        .join("\t")
+    }
+
+    fn parse(line: &str) -> Option<Entry> {
+        let mut fields = line.split('\t');
+        Some(Entry {
+            timestamp: fields.next()?.to_string(),
+            archive_id: fields.next()?.to_string(),
+            title: fields.next().unwrap_or_default().to_string(),
+            path: PathBuf::from(fields.next().unwrap_or_default()),
+            format: fields.next().unwrap_or_default().to_string(),
+            size: fields.next().and_then(|s| s.parse().ok()).unwrap_or(0),
+            url: fields.next().unwrap_or_default().to_string(),
+            uploader: fields.next().unwrap_or_default().to_string(),
+            seconds: fields.next().and_then(|s| s.parse().ok()).unwrap_or(0.0),
+            hash: fields
+                .next()
+                .filter(|hash| !hash.is_empty())
+                .map(str::to_string),
+            connections: fields.next().and_then(|s| s.parse().ok()),
+            ytdlp_version: fields
+                .next()
+                .filter(|version| !version.is_empty())
+                .map(str::to_string),
+            thumbnail: String::new(),
+            duration: None,
+            upload_date: None,
+        })
+    }
+}
+
+// `--print-to-file` template producing the line `Entry::from_ytdlp` expects.
+pub const PRINT_TEMPLATE: &str =
+    "after_move:%(extractor_key)s\t%(id)s\t%(title)s\t%(format_id)s\t%(webpage_url)s\t%(uploader)s\t%(thumbnail)s\t%(duration)s\t%(upload_date)s\t%(filepath)s";
+
+pub fn append(history_file: &Path, entry: &Entry) -> io::Result<()> {
+    let mut file = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(history_file)?;
+    writeln!(file, "{}", entry.to_line())
+}
+
+// One URL handed to yt-dlp, successful or not, for failure rates in `dlyt stats`.
+pub struct Attempt {
+    pub timestamp: String,
+    pub url: String,
+    // None when the URL downloaded fine
+    pub failure: Option<ErrorClass>,
+}
+
+pub fn record_attempt(
+    attempts_file: &Path,
+    url: &str,
+    failure: Option<ErrorClass>,
+) -> io::Result<()> {
+    let mut file = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(attempts_file)?;
+    writeln!(
+        file,
+        "{}\t{}\t{}",
+        date::format_timestamp(date::now()),
+        url,
+        failure.map_or("ok", ErrorClass::label)
+    )
+}
+
+pub fn load_attempts(attempts_file: &Path) -> io::Result<Vec<Attempt>> {
+    let contents = match fs::read_to_string(attempts_file) {
+        Ok(contents) => contents,
+        Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(err) => return Err(err),
+    };
+    Ok(contents
+        .lines()
+        .filter_map(|line| {
+            let mut fields = line.split('\t');
+            Some(Attempt {
+                timestamp: fields.next()?.to_string(),
+                url: fields.next()?.to_string(),
+                failure: ErrorClass::from_label(fields.next()?),
+            })
+        })
+        .collect())
+}
+
+pub fn load(history_file: &Path) -> io::Result<Vec<Entry>> {
+    match fs::read_to_string(history_file) {
+        Ok(contents) => Ok(contents.lines().filter_map(Entry::parse).collect()),
+        Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(Vec::new()),
+        Err(err) => Err(err),
+    }
+}
+
+// Rewrites the history, e.g. after `dlyt remux` moved files. Written next to it first so an
+// interruption cannot truncate it.
+pub fn save(history_file: &Path, entries: &[Entry]) -> io::Result<()> {
+    let temp = history_file.with_extension("tsv.tmp");
+    let mut contents = String::new();
+    for entry in entries {
+        contents.push_str(&entry.to_line());
+        contents.push('\n');
+    }
+    fs::write(&temp, contents)?;
+    fs::rename(&temp, history_file)
+}
+
+pub fn show(history_file: &Path, limit: usize) -> io::Result<()> {
+    let entries = load(history_file)?;
+    if entries.is_empty() {
+        println!("No downloads recorded yet in {}.", history_file.display());
+        return Ok(());
+    }
+
+    let skip = entries.len().saturating_sub(limit);
+    for entry in &entries[skip..] {
+        println!(
+            "{}  {}  {} [{}, {}]",
+            entry.timestamp,
+            entry.archive_id,
+            entry.title,
+            entry.format,
+            human_size(entry.size)
+        );
+        println!("    {}", entry.path.display());
+    }
+    Ok(())
+}
+
+// Checks every recorded file is still on disk with its recorded size, and lists archive
+// entries that predate the enriched history. Returns whether everything checked out.
+pub fn verify(history_file: &Path, archive_file: &Path) -> io::Result<bool> {
+    let entries = load(history_file)?;
+    let mut problems = 0;
+    let mut remote = 0;
+
+    for entry in &entries {
+        if deliver::is_remote(&entry.path) {
+            remote += 1;
+            continue;
+        }
+        match fs::metadata(&entry.path) {
+            Ok(meta) if meta.len() == entry.size => {}
+            Ok(meta) => {
+                problems += 1;
+                println!(
+                    "SIZE CHANGED  {}  {} (recorded {}, now {})",
+                    entry.archive_id,
+                    entry.path.display(),
+                    entry.size,
+                    meta.len()
+                );
+            }
+            Err(_) => {
+                problems += 1;
+                println!(
+                    "MISSING       {}  {}",
+                    entry.archive_id,
+                    entry.path.display()
+                );
+            }
+        }
+    }
+
+    let recorded: HashSet<&str> = entries.iter().map(|e| e.archive_id.as_str()).collect();
+    let archive = fs::read_to_string(archive_file).unwrap_or_default();
+    let unrecorded: Vec<&str> = archive
+        .lines()
+        .map(str::trim)
+        .filter(|id| !id.is_empty() && !recorded.contains(id))
+        .collect();
+    for id in &unrecorded {
+        println!("NO RECORD     {}", id);
+    }
+
+    println!(
+        "Checked {} recorded downloads: {} problem(s), {} archive entries without an enriched record.",
+        entries.len() - remote,
+        problems,
+        unrecorded.len()
+    );
+    if remote > 0 {
+        println!("Skipped {} downloads that were moved to a remote.", remote);
+    }
+    Ok(problems == 0)
+}
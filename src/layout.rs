@@ -0,0 +1,86 @@
+use crate::urls;
+
+// How the files of a URL are named and laid out, from `--layout` or a `layout=` directive.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Layout {
+    // `<title>.<ext>` in the collection's directory
+    Default,
+    // `Artist/Album/NN - Title.<ext>` with album tags and cover art, for releases on music sites
+    Music,
+    // `shorts/<collection>/<id>.<ext>` without sidecars, downloaded several at a time, for
+    // short-form videos whose titles say nothing
+    Shorts,
+}
+
+// Sites whose URLs get the music layout with `--layout auto`; subdomains count too, such as
+// `artist.bandcamp.com`.
+const MUSIC_SITES: &[&str] = &["bandcamp.com", "soundcloud.com"];
+// Sites that only have short-form videos, and YouTube's Shorts by their `/shorts/` path
+const SHORTS_SITES: &[&str] = &["tiktok.com", "instagram.com"];
+const SHORTS_PATH: &str = "youtube.com/shorts/";
+
+// Output template of the music layout. Tracks outside an album are filed under `Singles`, and a
+// playlist stands in for the album on sites without albums.
+pub const MUSIC_TEMPLATE: &str = "%(artist,uploader|Unknown Artist)s/%(album,playlist_title|Singles)s/%(track_number,playlist_index|0)02d - %(track,title)s.%(ext)s";
+// Output tree and template of the shorts layout, below the staging directory
+pub const SHORTS_DIR: &str = "shorts";
+pub const SHORTS_TEMPLATE: &str = "%(id)s.%(ext)s";
+
+// Where the album art goes, next to the tracks
+pub const MUSIC_COVER_TEMPLATE: &str =
+    "%(artist,uploader|Unknown Artist)s/%(album,playlist_title|Singles)s/cover.%(ext)s";
+
+// yt-dlp arguments of the music layout: tags filled in the same way as the directories, and
+// the cover art embedded and kept as a JPEG.
+pub const MUSIC_ARGS: &[&str] = &[
+    "--embed-metadata",
+    "--parse-metadata",
+    "%(album,playlist_title)s:%(album)s",
+    "--parse-metadata",
+    "%(album_artist,artist,uploader)s:%(album_artist)s",
+    "--parse-metadata",
+    "%(track_number,playlist_index)s:%(track_number)s",
+    "--write-thumbnail",
+    "--embed-thumbnail",
+    "--convert-thumbnails",
+    "jpg",
+];
+
+impl Layout {
+    pub fn from_name(name: &str) -> Option<Layout> {
+        match name {
+            "default" => Some(Layout::Default),
+            "music" => Some(Layout::Music),
+            "shorts" => Some(Layout::Shorts),
+            _ => None,
+        }
+    }
+
+    // The layout a URL gets: the configured one, or with None (`--layout auto`) the one for its
+    // site.
+    pub fn for_url(url: &str, configured: Option<Layout>) -> Layout {
+        configured.unwrap_or_else(|| {
+            let domain = urls::domain(url);
+            let on = |site: &&str| {
+                domain == *site
+                    || domain
+                        .strip_suffix(*site)
+                        .is_some_and(|sub| sub.ends_with('.'))
+            };
+            if MUSIC_SITES.iter().any(on) {
+                Layout::Music
+            } else if SHORTS_SITES.iter().any(on) || is_youtube_short(url) {
+                Layout::Shorts
+            } else {
+                Layout::Default
+            }
+        })
+    }
+}
+
+fn is_youtube_short(url: &str) -> bool {
+    let rest = url.split_once("://").map_or(url, |(_, rest)| rest);
+    ["", "www.", "m."]
+        .iter()
+        .any(|prefix| rest.starts_with(&format!("{}{}", prefix, SHORTS_PATH)))
+}
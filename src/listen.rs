@@ -0,0 +1,257 @@
+use std::collections::hash_map::RandomState;
+use std::fs;
+use std::hash::{BuildHasher, Hasher};
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::net::{Ipv4Addr, TcpListener, TcpStream};
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{self, Sender};
+use std::thread;
+use std::time::Duration;
+
+use crate::cli::Options;
+use crate::json;
+use crate::run::Runner;
+use crate::urls::{self, Directives, UrlEntry};
+
+// Largest request body accepted; a URL and a few fields.
+const MAX_BODY: usize = 16 * 1024;
+
+// Serves `POST /add` on 127.0.0.1 for a bookmarklet or browser extension. URLs are appended
+// to a .urls file, or with `queue=now` downloaded right away by a worker thread.
+pub fn listen(opts: &Options, token_file: &Path) -> io::Result<()> {
+    let token = match &opts.listen_token {
+        Some(token) => token.clone(),
+        None => load_or_create_token(token_file)?,
+    };
+    let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, opts.listen_port))?;
+    let address = format!("http://127.0.0.1:{}", opts.listen_port);
+    println!("Listening on {}/add", address);
+    println!("Bookmarklet, adds the current tab to default.urls:");
+    println!(
+        "javascript:fetch('{}/add',{{method:'POST',body:new URLSearchParams({{token:'{}',url:location.href}})}}).then(r=>r.json()).then(r=>alert(r.message))",
+        address, token
+    );
+
+    thread::scope(|scope| {
+        let (queue, pending) = mpsc::channel::<UrlEntry>();
+        scope.spawn(move || {
+            for entry in pending {
+                let result = Runner::new(opts).and_then(|mut runner| {
+                    runner.run_entry(entry)?;
+                    runner.finish()
+                });
+                if let Err(err) = result {
+                    println!("Queued download failed: {}", err);
+                }
+            }
+        });
+
+        for stream in listener.incoming() {
+            let mut stream = match stream {
+                Ok(stream) => stream,
+                Err(err) => {
+                    println!("Accepting a connection failed: {}", err);
+                    continue;
+                }
+            };
+            let _ = stream.set_read_timeout(Some(Duration::from_secs(10)));
+            let (status, message) = match handle(opts, &mut stream, &token, &queue) {
+                Ok(response) => response,
+                Err(err) => ("400 Bad Request", err.to_string()),
+            };
+            let _ = respond(&mut stream, status, &message);
+        }
+        Ok(())
+    })
+}
+
+// Handles one request, returning the HTTP status and a message for the caller.
+fn handle(
+    opts: &Options,
+    stream: &mut TcpStream,
+    token: &str,
+    queue: &Sender<UrlEntry>,
+) -> io::Result<(&'static str, String)> {
+    let mut reader = BufReader::new(stream);
+    let mut request_line = String::new();
+    reader.read_line(&mut request_line)?;
+    let mut parts = request_line.split_whitespace();
+    let method = parts.next().unwrap_or_default().to_string();
+    let path = parts.next().unwrap_or_default().to_string();
+
+    let mut content_length = 0;
+    let mut authorization = None;
+    loop {
+        let mut header = String::new();
+        if reader.read_line(&mut header)? == 0 || header.trim().is_empty() {
+            break;
+        }
+        if let Some((name, value)) = header.split_once(':') {
+            match name.trim().to_ascii_lowercase().as_str() {
+                "content-length" => content_length = value.trim().parse().unwrap_or(0),
+                "authorization" => {
+                    authorization = value.trim().strip_prefix("Bearer ").map(str::to_string)
+                }
+                _ => {}
+            }
+        }
+    }
+
+    // CORS preflight from an extension sending the token as a header
+    if method == "OPTIONS" {
+        return Ok(("200 OK", String::new()));
+    }
+    if method != "POST" || path != "/add" {
+        return Ok(("404 Not Found", "Only POST /add is served.".to_string()));
+    }
+    if content_length > MAX_BODY {
+        return Ok(("413 Payload Too Large", "Request too large.".to_string()));
+    }
+
+    let mut body = vec![0; content_length];
+    reader.read_exact(&mut body)?;
+    let params = parse_form(&String::from_utf8_lossy(&body));
+    let param = |name: &str| {
+        params
+            .iter()
+            .find(|(key, _)| key == name)
+            .map(|(_, value)| value.as_str())
+    };
+
+    let given = authorization
+        .as_deref()
+        .or(param("token"))
+        .unwrap_or_default();
+    if !same(given, token) {
+        return Ok(("401 Unauthorized", "Invalid token.".to_string()));
+    }
+
+    let url = param("url").unwrap_or_default().trim();
+    if !url.starts_with("http://") && !url.starts_with("https://") {
+        return Ok(("400 Bad Request", "Expected an http(s) url.".to_string()));
+    }
+    let name = param("file").unwrap_or("default");
+    let Some(file) = urls_file(&opts.urls_dir, name) else {
+        return Ok((
+            "400 Bad Request",
+            format!("Invalid .urls file name: {}", name),
+        ));
+    };
+
+    if param("queue") == Some("now") {
+        let entry = UrlEntry {
+            url: url.to_string(),
+            directives: Directives::default(),
+            file,
+            line: 0,
+            parent: None,
+        };
+        println!("Queued {} for {}.", url, name);
+        queue
+            .send(entry)
+            .map_err(|_| io::Error::other("the download queue stopped"))?;
+        return Ok(("202 Accepted", format!("Downloading {}", url)));
+    }
+
+    if urls::append(&file, url)? {
+        println!("Added {} to {}.", url, file.display());
+        Ok(("200 OK", format!("Added to {}.urls", name)))
+    } else {
+        Ok(("200 OK", format!("Already in {}.urls", name)))
+    }
+}
+
+fn respond(stream: &mut TcpStream, status: &str, message: &str) -> io::Result<()> {
+    let body = format!(
+        "{{\"ok\":{},\"message\":{}}}",
+        status.starts_with('2'),
+        json::quote(message)
+    );
+    write!(
+        stream,
+        "HTTP/1.1 {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nAccess-Control-Allow-Origin: *\r\nAccess-Control-Allow-Methods: POST, OPTIONS\r\nAccess-Control-Allow-Headers: Authorization, Content-Type\r\nConnection: close\r\n\r\n{}",
+        status,
+        body.len(),
+        body
+    )
+}
+
+// `urls/<name>.urls` for a plain file name, None for anything that could leave the directory.
+fn urls_file(urls_dir: &Path, name: &str) -> Option<PathBuf> {
+    let name = name.strip_suffix(".urls").unwrap_or(name);
+    let valid = !name.is_empty()
+        && !name.starts_with('.')
+        && name
+            .chars()
+            .all(|c| c.is_alphanumeric() || matches!(c, '-' | '_' | '.' | ' '));
+    valid.then(|| urls_dir.join(format!("{}.urls", name)))
+}
+
+// `application/x-www-form-urlencoded` pairs.
+fn parse_form(body: &str) -> Vec<(String, String)> {
+    body.split('&')
+        .filter(|pair| !pair.is_empty())
+        .map(|pair| {
+            let (key, value) = pair.split_once('=').unwrap_or((pair, ""));
+            (percent_decode(key), percent_decode(value))
+        })
+        .collect()
+}
+
+fn percent_decode(value: &str) -> String {
+    let bytes = value.as_bytes();
+    let mut out = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        match bytes[i] {
+            b'+' => out.push(b' '),
+            b'%' if i + 2 < bytes.len() => {
+                let hex = std::str::from_utf8(&bytes[i + 1..i + 3]).unwrap_or_default();
+                match u8::from_str_radix(hex, 16) {
+                    Ok(byte) => {
+                        out.push(byte);
+                        i += 2;
+                    }
+                    Err(_) => out.push(b'%'),
+                }
+            }
+            byte => out.push(byte),
+        }
+        i += 1;
+    }
+    String::from_utf8_lossy(&out).to_string()
+}
+
+// Compares tokens without stopping at the first difference.
+fn same(given: &str, token: &str) -> bool {
+    given.len() == token.len()
+        && given
+            .bytes()
+            .zip(token.bytes())
+            .fold(0, |diff, (a, b)| diff | (a ^ b))
+            == 0
+}
+
+// The token saved by an earlier `dlyt listen`, or a new random one.
+fn load_or_create_token(token_file: &Path) -> io::Result<String> {
+    if let Ok(token) = fs::read_to_string(token_file) {
+        if !token.trim().is_empty() {
+            return Ok(token.trim().to_string());
+        }
+    }
+    // RandomState is seeded from the OS on every call
+    let token: String = (0..2)
+        .map(|_| {
+            let mut hasher = RandomState::new().build_hasher();
+            hasher.write_u128(
+                std::time::SystemTime::now()
+                    .duration_since(std::time::UNIX_EPOCH)
+                    .map_or(0, |d| d.as_nanos()),
+            );
+            format!("{:016x}", hasher.finish())
+        })
+        .collect();
+    fs::write(token_file, format!("{}\n", token))?;
+    println!("Created a new token in {}.", token_file.display());
+    Ok(token)
+}
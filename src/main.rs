@@ -1,11 +1,270 @@
+// println! for messages meant for people, which --porcelain leaves out.
+macro_rules! say {
+    ($($arg:tt)*) => {
+        if !$crate::PORCELAIN.load(std::sync::atomic::Ordering::Relaxed) {
+            println!($($arg)*);
+        }
+    };
+}
+
+mod archive;
+mod aria2;
+mod auth;
+mod clean;
+mod cli;
+mod collision;
+mod config;
+mod container;
+mod date;
+mod deliver;
+mod download;
+mod du;
+mod dupes;
+mod export;
+mod failure;
+mod hash;
+mod history;
+mod json;
+mod layout;
+mod listen;
+mod manifest;
+mod notify;
+mod paths;
+mod playlist;
+mod postprocess;
+mod probe;
+mod progress;
+mod remux;
+mod report;
+mod resume;
+mod retry;
+mod run;
+mod shared;
+mod stats;
+mod twitch;
+mod units;
+mod urls;
+mod ytdlp;
+
+use std::env;
 use std::fs::{self, File};
-use std::io::{self, BufRead, Write};
+use std::io::{self, Write};
 use std::path::{Path, PathBuf};
-use std::process::{exit, Command};
+use std::process::exit;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::thread;
+use std::time::Duration;
+
+use cli::Options;
+use run::Runner;
+
+const CONFIG_FILE: &str = "dlyt.conf";
+const URLS_DIR: &str = "urls";
+const VIDEOS_DIR: &str = "videos";
+// Downloads stay here until they are complete and post-processed
+const STAGING_DIR: &str = ".staging";
+// Videos of a collection directory, for tools that read it without the history
+const INDEX_FILE: &str = "index.json";
+// Untouched downloads kept by --keep-original, next to the converted files
+const ORIGINALS_DIR: &str = "originals";
+const ARCHIVE_FILE: &str = "downloaded.txt";
+// Enriched record of every download, kept next to the yt-dlp archive.
+const HISTORY_FILE: &str = "history.tsv";
+// Every URL handed to yt-dlp and whether it failed, for `dlyt stats`
+const ATTEMPTS_FILE: &str = "attempts.tsv";
+// URLs that failed because they need an account, collected for a later authenticated pass
+const NEEDS_AUTH_FILE: &str = "needs-auth.urls";
+// yt-dlp cache holding the OAuth2 refresh token from `dlyt login`
+const OAUTH_DIR: &str = "oauth";
+// URLs that failed with transient errors, waiting for another attempt
+const RETRY_FILE: &str = "retry.tsv";
+// URLs a run stopped before (--max-runtime, --max-downloads), for `--resume`
+const RESUME_FILE: &str = "resume.tsv";
+// URLs being downloaded, so the next run continues one a crash or Ctrl-C cut short
+const DOWNLOADING_FILE: &str = "downloading.tsv";
+// The file names yt-dlp is writing for each of those URLs, which tell their partial files apart
+const DOWNLOADING_DIR: &str = "downloading";
+// Token `dlyt listen` generated for the bookmarklet
+const LISTEN_TOKEN_FILE: &str = "listen.token";
+// Empty build context of `dlyt container-init`
+const CONTAINER_DIR: &str = "container";
+// URLs a failure policy dropped, skipped by later runs
+const DROPPED_FILE: &str = "dropped.tsv";
+
+// What a root keeps in its state directory
+const STATE_FILES: &[&str] = &[
+    ARCHIVE_FILE,
+    HISTORY_FILE,
+    ATTEMPTS_FILE,
+    OAUTH_DIR,
+    RETRY_FILE,
+    RESUME_FILE,
+    DOWNLOADING_FILE,
+    DOWNLOADING_DIR,
+    LISTEN_TOKEN_FILE,
+    CONTAINER_DIR,
+    DROPPED_FILE,
+];
+
+// Set from --porcelain, so stdout only has the result lines
+static PORCELAIN: AtomicBool = AtomicBool::new(false);
 
 fn main() -> io::Result<()> {
-    // Check for yt-dlp and ffmpeg dependencies
-    if !command_exists("yt-dlp") || !command_exists("ffmpeg") {
+    let mut opts = match Options::from_args() {
+        Ok(opts) => opts,
+        Err(err) => {
+            eprintln!("{}", err);
+            eprintln!("Run with --help to see the available options.");
+            exit(2);
+        }
+    };
+    PORCELAIN.store(opts.porcelain, Ordering::Relaxed);
+
+    // Everything relative, from urls/ to paths in the config file, is relative to the root
+    fs::create_dir_all(&opts.root)?;
+    env::set_current_dir(&opts.root)?;
+    opts.state_dir = paths::state_dir(Path::new("."));
+    if opts.urls_dir.is_dir() && paths::adopt_shared_state(&opts.state_dir, STATE_FILES)? {
+        say!(
+            "Moved the archive, history and queues of earlier runs to {}, this root's own state directory.",
+            opts.state_dir.display()
+        );
+    }
+    fs::create_dir_all(&opts.state_dir)?;
+    ytdlp::init(&paths::data_dir().unwrap_or_else(|| opts.state_dir.clone()));
+    if opts.in_container {
+        if let Err(err) = container::init(&opts) {
+            eprintln!("{}", err);
+            exit(2);
+        }
+    }
+
+    match opts.command {
+        cli::Command::Run => run(&opts),
+        cli::Command::Daemon => daemon(&opts),
+        cli::Command::Listen => {
+            if prepare(&opts)? {
+                listen::listen(&opts, &opts.state_file(LISTEN_TOKEN_FILE))?;
+            }
+            Ok(())
+        }
+        cli::Command::History if opts.json => {
+            export::recent(&opts.state_file(HISTORY_FILE), opts.limit)
+        }
+        cli::Command::History => history::show(&opts.state_file(HISTORY_FILE), opts.limit),
+        cli::Command::Verify => {
+            if !history::verify(&opts.state_file(HISTORY_FILE), &opts.archive_file())? {
+                exit(1);
+            }
+            Ok(())
+        }
+        cli::Command::Login => {
+            if !auth::login(&opts.state_file(OAUTH_DIR))? {
+                exit(1);
+            }
+            Ok(())
+        }
+        cli::Command::Stats => stats::show(
+            &opts.state_file(HISTORY_FILE),
+            &opts.state_file(ATTEMPTS_FILE),
+            opts.limit,
+        ),
+        cli::Command::Export => {
+            match export::export(
+                &opts.state_file(HISTORY_FILE),
+                opts.export_format,
+                opts.since,
+            ) {
+                // Piped into `head` and the like
+                Err(err) if err.kind() == io::ErrorKind::BrokenPipe => Ok(()),
+                result => result,
+            }
+        }
+        cli::Command::Dupes => dupes::show(&opts.state_file(HISTORY_FILE)),
+        cli::Command::Du => match opts.final_dir() {
+            Some(output) => du::show(&output, opts.sort, opts.json),
+            None => {
+                println!("`du` only works for local output directories.");
+                exit(2);
+            }
+        },
+        cli::Command::Remux => {
+            if !container::is_active() && !command_exists("ffmpeg") {
+                println!("`remux` needs ffmpeg, which is not installed.");
+                exit(2);
+            }
+            if !container::check_image() {
+                exit(2);
+            }
+            let dir = command_dir(&opts, "remux");
+            if !remux::remux(
+                &dir,
+                opts.remux_to.as_deref(),
+                &opts.state_file(HISTORY_FILE),
+            )? {
+                exit(1);
+            }
+            Ok(())
+        }
+        cli::Command::Playlist => {
+            let dir = command_dir(&opts, "playlist");
+            if playlist::write_all(&dir, opts.playlist_order)? == 0 {
+                println!("No index.json below {}, nothing to list.", dir.display());
+            }
+            Ok(())
+        }
+        cli::Command::Update => {
+            let updated = if opts.rollback {
+                ytdlp::rollback()?
+            } else if !command_exists("curl") {
+                println!("`update` needs curl, which is not installed.");
+                false
+            } else {
+                ytdlp::update(opts.update_channel)?
+            };
+            if !updated {
+                exit(1);
+            }
+            Ok(())
+        }
+        cli::Command::ContainerInit => {
+            if !container::build(&opts)? {
+                exit(1);
+            }
+            Ok(())
+        }
+        cli::Command::Clean => {
+            if !clean::clean(&opts)? {
+                exit(1);
+            }
+            Ok(())
+        }
+    }
+}
+
+// The DIR of `dlyt remux|playlist [DIR]`, the output directory by default. Exits if there is
+// none.
+fn command_dir(opts: &Options, command: &str) -> PathBuf {
+    let Some(dir) = opts.dir.clone().or_else(|| opts.final_dir()) else {
+        println!("Give the directory: dlyt {} <DIR>.", command);
+        exit(2);
+    };
+    if !dir.is_dir() {
+        println!("{} is not a directory.", dir.display());
+        exit(2);
+    }
+    dir
+}
+
+// Checks dependencies and creates the urls directory on first use. Returns false when there
+// is nothing to run yet.
+fn prepare(opts: &Options) -> io::Result<bool> {
+    // With --in-container the image brings yt-dlp, ffmpeg and aria2c
+    if container::is_active() {
+        if !container::check_image() {
+            return Ok(false);
+        }
+    } else if !(ytdlp::is_managed() || command_exists("yt-dlp")) || !command_exists("ffmpeg") {
         println!("The required dependencies yt-dlp and ffmpeg are not installed.");
         println!("Please install them before running this program.");
         println!("On Linux, you can use the following commands:");
@@ -15,93 +274,134 @@ fn main() -> io::Result<()> {
         println!("On Windows, you can download the executables and add them to your PATH:");
         println!("yt-dlp: https://github.com/yt-dlp/yt-dlp/releases/latest");
         println!("ffmpeg: https://www.gyan.dev/ffmpeg/builds/");
-        return Ok(());
+        println!("Or let dlyt install and update its own yt-dlp, checked against the published checksums: dlyt update");
+        println!("Or run them in a container with Docker or Podman: dlyt container-init, then dlyt run --in-container");
+        return Ok(false);
+    }
+
+    if opts.downloader == download::Downloader::Aria2c
+        && !container::is_active()
+        && !command_exists("aria2c")
+    {
+        println!("--downloader aria2c needs aria2c, which is not installed.");
+        return Ok(false);
+    }
+
+    if matches!(opts.shared_archive, Some(shared::SharedArchive::Redis(_)))
+        && !command_exists("redis-cli")
+    {
+        println!("A redis:// --shared-archive needs redis-cli, which is not installed.");
+        return Ok(false);
+    }
+
+    if opts.upload.is_some() && !command_exists("rclone") {
+        println!("--upload needs rclone, which is not installed. See https://rclone.org/install/");
+        return Ok(false);
+    }
+
+    if opts.output.starts_with("sftp://") {
+        if opts.sftp_target().is_none() {
+            println!(
+                "Invalid SFTP destination: {}. Use sftp://user@host/path.",
+                opts.output
+            );
+            return Ok(false);
+        }
+        if !command_exists("sftp") {
+            println!(
+                "An sftp:// destination needs the OpenSSH sftp client, which is not installed."
+            );
+            return Ok(false);
+        }
     }
 
-    let dir_path = "urls";
-    let base_dir = "videos";
-    let archive_file = "downloaded.txt";
+    if !opts.notify.is_empty() && !command_exists("curl") {
+        println!("The notifiers in the config file need curl, which is not installed.");
+        return Ok(false);
+    }
+
+    if opts.oauth && !auth::is_logged_in(&opts.state_file(OAUTH_DIR)) {
+        println!("--oauth is set but no login was found. Run `dlyt login` first.");
+        exit(2);
+    }
 
-    if !Path::new(dir_path).exists() {
+    let dir_path = opts.urls_dir.as_path();
+
+    if !dir_path.exists() {
         fs::create_dir(dir_path)?;
-        println!("Created directory: {}. You can create your own .urls files in this directory. The name of the file will be used as the subdirectory for the downloaded videos.", dir_path);
+        say!("Created directory: {}. You can create your own .urls files in this directory. The name of the file will be used as the subdirectory for the downloaded videos.", dir_path.display());
 
-        let default_file = Path::new(dir_path).join("default.urls");
+        let default_file = dir_path.join("default.urls");
         let mut file = File::create(&default_file)?;
         writeln!(file, "# Add your URLs here, one per line. This is the default file, videos will be downloaded to the base directory.")?;
-        println!("Created file: {}. You can add URLs to this file for downloading videos. For different subdirectories, create a new .urls file with the name of the subdirectory.", default_file.display());
+        say!("Created file: {}. You can add URLs to this file for downloading videos. For different subdirectories, create a new .urls file with the name of the subdirectory.", default_file.display());
 
-        exit(0);
+        return Ok(false);
     }
 
-    let default_file = Path::new(dir_path).join("default.urls");
+    let default_file = dir_path.join("default.urls");
     if !default_file.exists() {
         let mut file = File::create(&default_file)?;
         writeln!(file, "# Add your URLs here, one per line. This is the default file, videos will be downloaded to the base directory.")?;
-        println!("Created file: {}. You can add URLs to this file for downloading videos. For different subdirectories, create a new .urls file with the name of the subdirectory.", default_file.display());
-        exit(0);
+        say!("Created file: {}. You can add URLs to this file for downloading videos. For different subdirectories, create a new .urls file with the name of the subdirectory.", default_file.display());
+        return Ok(false);
     }
 
-    let mut urls_exist = false;
-
-    for entry in fs::read_dir(dir_path)? {
-        let entry = entry?;
-        let path = entry.path();
-        if path.is_file() {
-            let file_stem = path.file_stem().unwrap().to_str().unwrap();
-            let output_dir = if file_stem == "default" {
-                PathBuf::from(base_dir)
-            } else {
-                PathBuf::from(base_dir).join(file_stem)
-            };
-            fs::create_dir_all(&output_dir)?;
-
-            let file = File::open(&path)?;
-            let reader = io::BufReader::new(file);
-
-            for line in reader.lines() {
-                let url = line?;
-                if !url.starts_with('#') {
-                    urls_exist = true;
-                    break;
-                }
-            }
+    Ok(true)
+}
 
-            if urls_exist {
-                let status = Command::new("yt-dlp")
-                    .arg("-a")
-                    .arg(path.to_str().unwrap())
-                    .arg("--download-archive")
-                    .arg(archive_file)
-                    .arg("-f")
-                    .arg("bestvideo+bestaudio")
-                    .arg("--prefer-ffmpeg")
-                    .arg("--write-description")
-                    .arg("--add-metadata")
-                    .arg("--write-auto-sub")
-                    .arg("--embed-subs")
-                    .arg("-o")
-                    .arg(output_dir.join("%(title)s.%(ext)s").to_str().unwrap())
-                    .status()?;
-
-                println!("Download finished with exit status: {}", status);
-            }
+fn run(opts: &Options) -> io::Result<()> {
+    if prepare(opts)? {
+        let status = sync(opts)?;
+        if status != 0 {
+            exit(status);
         }
     }
+    Ok(())
+}
+
+// Returns the exit status, see Runner::finish.
+fn sync(opts: &Options) -> io::Result<i32> {
+    let mut runner = Runner::new(opts)?;
+    if !runner.sync()? {
+        say!("No URLs found in the .urls files. Please add URLs to the .urls files for downloading videos. Each URL should be on a new line. Lines starting with '#' are considered comments and are ignored.");
+        return Ok(0);
+    }
+    if opts.dry_run {
+        return Ok(0);
+    }
+    runner.finish()
+}
 
-    if !urls_exist {
-        println!("No URLs found in the .urls files. Please add URLs to the .urls files for downloading videos. Each URL should be on a new line. Lines starting with '#' are considered comments and are ignored.");
+// Syncs every `--interval` and, in between, re-attempts failed URLs as their backoff expires.
+fn daemon(opts: &Options) -> io::Result<()> {
+    if !prepare(opts)? {
+        return Ok(());
     }
 
-    fn command_exists(cmd: &str) -> bool {
-        let output = if cfg!(target_os = "windows") {
-            Command::new("where").arg("/Q").arg(cmd).output()
-        } else {
-            Command::new("which").arg(cmd).output()
-        };
+    loop {
+        let next_sync = date::now() + opts.interval;
+        sync(opts)?;
+
+        loop {
+            let mut runner = Runner::new(opts)?;
+            let retried = runner.retry_due()?;
+            let next_retry = runner.next_retry();
+            if retried > 0 {
+                runner.finish()?;
+            }
 
-        output.map_or(false, |o| o.status.success())
+            let now = date::now();
+            if now >= next_sync {
+                break;
+            }
+            let wake = next_retry.map_or(next_sync, |at| at.max(now + 60).min(next_sync));
+            say!("Next sync in {}.", units::human_duration(next_sync - now));
+            thread::sleep(Duration::from_secs(wake - now));
+        }
     }
+}
 
-    Ok(())
+fn command_exists(cmd: &str) -> bool {
+    paths::find_program(cmd).is_some()
 }
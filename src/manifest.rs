@@ -0,0 +1,116 @@
+use std::fs;
+use std::io;
+use std::path::Path;
+
+use crate::history;
+use crate::json::{self, Value};
+use crate::INDEX_FILE;
+
+// One video listed in a directory's index.json.
+pub struct Item {
+    pub id: String,
+    pub title: String,
+    pub url: String,
+    pub duration: Option<f64>,
+    pub size: u64,
+    // YYYY-MM-DD when the site reports it
+    pub uploaded: Option<String>,
+    pub downloaded: String,
+    // File name inside the directory
+    pub file: String,
+}
+
+impl Item {
+    fn from_json(item: &Value) -> Option<Item> {
+        let text = |key| item.get(key).and_then(Value::as_str).map(str::to_string);
+        Some(Item {
+            id: text("id")?,
+            title: text("title").unwrap_or_default(),
+            url: text("url").unwrap_or_default(),
+            duration: item.get("duration").and_then(Value::as_f64),
+            size: item.get("size").and_then(Value::as_f64).unwrap_or(0.0) as u64,
+            uploaded: text("uploaded"),
+            downloaded: text("downloaded").unwrap_or_default(),
+            file: text("file")?,
+        })
+    }
+
+    fn to_json(&self) -> String {
+        format!(
+            "{{\"id\":{},\"title\":{},\"url\":{},\"duration\":{},\"size\":{},\"uploaded\":{},\"downloaded\":{},\"file\":{}}}",
+            json::quote(&self.id),
+            json::quote(&self.title),
+            json::quote(&self.url),
+            self.duration
+                .map_or_else(|| "null".to_string(), |d| format!("{:.1}", d)),
+            self.size,
+            self.uploaded
+                .as_deref()
+                .map_or_else(|| "null".to_string(), json::quote),
+            json::quote(&self.downloaded),
+            json::quote(&self.file)
+        )
+    }
+}
+
+// Adds a finished file to the index.json of the directory it was saved in, replacing an
+// earlier entry for the same file, so tools can read a collection without the history.
+pub fn add(record: &history::Entry) -> io::Result<()> {
+    let (Some(dir), Some(file)) = (record.path.parent(), record.path.file_name()) else {
+        return Ok(());
+    };
+    let index_file = dir.join(INDEX_FILE);
+    let file = file.to_string_lossy().to_string();
+
+    let mut items = load(dir)?;
+    items.retain(|item| item.file != file);
+    items.push(Item {
+        id: record.archive_id.clone(),
+        title: record.title.clone(),
+        url: record.url.clone(),
+        duration: record.duration,
+        size: record.size,
+        uploaded: record.upload_date.clone(),
+        downloaded: record.timestamp.clone(),
+        file,
+    });
+    save(&index_file, &items)
+}
+
+// The videos listed in the index.json of `dir`, none if there is none.
+pub fn load(dir: &Path) -> io::Result<Vec<Item>> {
+    let index_file = dir.join(INDEX_FILE);
+    let contents = match fs::read_to_string(&index_file) {
+        Ok(contents) => contents,
+        Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(err) => return Err(err),
+    };
+    let index = json::parse(&contents).map_err(|err| {
+        io::Error::other(format!(
+            "{} is not valid JSON: {}",
+            index_file.display(),
+            err
+        ))
+    })?;
+    Ok(index
+        .get("videos")
+        .map(Value::as_array)
+        .unwrap_or_default()
+        .iter()
+        .filter_map(Item::from_json)
+        .collect())
+}
+
+// One video per line, and written next to the index first so readers never see half of it.
+fn save(index_file: &Path, items: &[Item]) -> io::Result<()> {
+    let lines: Vec<String> = items
+        .iter()
+        .map(|item| format!("    {}", item.to_json()))
+        .collect();
+    let temp = index_file.with_extension("json.tmp");
+    fs::write(
+        &temp,
+        format!("{{\n  \"videos\": [\n{}\n  ]\n}}\n", lines.join(",\n")),
+    )?;
+    fs::rename(&temp, index_file)
+}
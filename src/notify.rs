@@ -0,0 +1,493 @@
+use std::fs;
+use std::io::{self, Write};
+use std::process::{self, Command, Stdio};
+
+use crate::failure::ErrorClass;
+use crate::report::Report;
+use crate::units::human_size;
+use crate::urls::UrlEntry;
+use crate::{date, history, json};
+
+// Which runs a notifier reports on.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Severity {
+    // Every run
+    All,
+    // Runs that downloaded something or had a failure
+    Changes,
+    // Runs with at least one failure
+    Failures,
+}
+
+impl Severity {
+    fn from_name(name: &str) -> Option<Severity> {
+        match name {
+            "all" => Some(Severity::All),
+            "changes" => Some(Severity::Changes),
+            "failures" => Some(Severity::Failures),
+            _ => None,
+        }
+    }
+
+    fn applies_to(self, report: &Report) -> bool {
+        match self {
+            Severity::All => true,
+            Severity::Changes => !report.files.is_empty() || report.failed() > 0,
+            Severity::Failures => report.failed() > 0,
+        }
+    }
+}
+
+// What a chat notifier posts about.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Events {
+    // A message per finished file or failed URL
+    Downloads,
+    // A summary per run
+    Runs,
+    Both,
+}
+
+impl Events {
+    fn from_name(name: &str) -> Option<Events> {
+        match name {
+            "downloads" => Some(Events::Downloads),
+            "runs" => Some(Events::Runs),
+            "both" => Some(Events::Both),
+            _ => None,
+        }
+    }
+
+    fn downloads(self) -> bool {
+        self != Events::Runs
+    }
+
+    fn runs(self) -> bool {
+        self != Events::Downloads
+    }
+}
+
+#[derive(Debug, Clone)]
+pub enum Service {
+    // Webhook URL from the channel's integration settings
+    Discord { webhook: String },
+    // Bot token from @BotFather and the chat id to post to
+    Telegram { token: String, chat: String },
+}
+
+// A Discord or Telegram notifier, configured in `[notify.discord]` or `[notify.telegram]`.
+#[derive(Debug, Clone)]
+pub struct Chat {
+    pub service: Service,
+    pub events: Events,
+    pub severity: Severity,
+}
+
+impl Chat {
+    fn new(service: Service) -> Chat {
+        Chat {
+            service,
+            events: Events::Runs,
+            severity: Severity::Changes,
+        }
+    }
+
+    fn set(&mut self, key: &str, value: &str) -> Result<(), String> {
+        match (&mut self.service, key) {
+            (Service::Discord { webhook }, "webhook") => *webhook = value.to_string(),
+            (Service::Telegram { token, .. }, "token") => *token = value.to_string(),
+            (Service::Telegram { chat, .. }, "chat") => *chat = value.to_string(),
+            (_, "events") => {
+                self.events = Events::from_name(value).ok_or_else(|| {
+                    format!(
+                        "invalid events `{}` (expected downloads, runs or both)",
+                        value
+                    )
+                })?
+            }
+            (_, "severity") => self.severity = parse_severity(value)?,
+            _ => return Err(format!("unknown {} setting `{}`", self.name(), key)),
+        }
+        Ok(())
+    }
+
+    fn name(&self) -> &'static str {
+        match self.service {
+            Service::Discord { .. } => "discord",
+            Service::Telegram { .. } => "telegram",
+        }
+    }
+
+    fn validate(&self) -> Result<(), String> {
+        match &self.service {
+            Service::Discord { webhook } if webhook.is_empty() => {
+                Err("[notify.discord] needs webhook".to_string())
+            }
+            Service::Telegram { token, chat } if token.is_empty() || chat.is_empty() => {
+                Err("[notify.telegram] needs token and chat".to_string())
+            }
+            _ => Ok(()),
+        }
+    }
+
+    fn send(&self, message: &Message) {
+        let result = match &self.service {
+            Service::Discord { webhook } => send_discord(webhook, message),
+            Service::Telegram { token, chat } => send_telegram(token, chat, message),
+        };
+        if let Err(err) = result {
+            say!("Posting to {} failed: {}", self.name(), err);
+        }
+    }
+}
+
+// One chat post.
+struct Message {
+    title: String,
+    text: String,
+    link: Option<String>,
+    thumbnail: Option<String>,
+    failed: bool,
+}
+
+// Run digest sent through an SMTP server with curl, configured in `[notify.email]`.
+#[derive(Debug, Clone)]
+pub struct Email {
+    // smtp://host:587 (STARTTLS is required) or smtps://host:465
+    pub server: String,
+    pub username: Option<String>,
+    pub password: Option<String>,
+    pub from: String,
+    pub to: Vec<String>,
+    pub severity: Severity,
+}
+
+// Notifiers configured in `[notify.<backend>]` sections.
+#[derive(Debug, Clone, Default)]
+pub struct Notifiers {
+    pub email: Option<Email>,
+    pub discord: Option<Chat>,
+    pub telegram: Option<Chat>,
+}
+
+impl Notifiers {
+    pub fn set(&mut self, backend: &str, key: &str, value: &str) -> Result<(), String> {
+        match backend {
+            "email" => {
+                let email = self.email.get_or_insert_with(|| Email {
+                    server: String::new(),
+                    username: None,
+                    password: None,
+                    from: String::new(),
+                    to: Vec::new(),
+                    severity: Severity::Changes,
+                });
+                match key {
+                    "server" => email.server = value.to_string(),
+                    "username" => email.username = Some(value.to_string()),
+                    "password" => email.password = Some(value.to_string()),
+                    "from" => email.from = value.to_string(),
+                    "to" => {
+                        email.to = value
+                            .split(',')
+                            .map(str::trim)
+                            .filter(|to| !to.is_empty())
+                            .map(str::to_string)
+                            .collect()
+                    }
+                    "severity" => email.severity = parse_severity(value)?,
+                    _ => return Err(format!("unknown email setting `{}`", key)),
+                }
+            }
+            "discord" => self
+                .discord
+                .get_or_insert_with(|| {
+                    Chat::new(Service::Discord {
+                        webhook: String::new(),
+                    })
+                })
+                .set(key, value)?,
+            "telegram" => self
+                .telegram
+                .get_or_insert_with(|| {
+                    Chat::new(Service::Telegram {
+                        token: String::new(),
+                        chat: String::new(),
+                    })
+                })
+                .set(key, value)?,
+            _ => {
+                return Err(format!(
+                    "unknown notifier `{}` (expected email, discord or telegram)",
+                    backend
+                ))
+            }
+        }
+        Ok(())
+    }
+
+    // Checks required settings once the whole config file was read.
+    pub fn validate(&self) -> Result<(), String> {
+        if let Some(email) = &self.email {
+            if email.server.is_empty() || email.from.is_empty() || email.to.is_empty() {
+                return Err("[notify.email] needs server, from and to".to_string());
+            }
+        }
+        self.chats().try_for_each(Chat::validate)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.email.is_none() && self.chats().next().is_none()
+    }
+
+    fn chats(&self) -> impl Iterator<Item = &Chat> {
+        self.discord.iter().chain(&self.telegram)
+    }
+
+    // Posts a finished file to the chats that want per-download messages.
+    pub fn downloaded(&self, entry: &history::Entry) {
+        let message = Message {
+            title: format!("Downloaded: {}", entry.title),
+            text: format!(
+                "{} [{}]\n{}",
+                if entry.uploader.is_empty() || entry.uploader == "NA" {
+                    "Unknown uploader"
+                } else {
+                    &entry.uploader
+                },
+                human_size(entry.size),
+                entry.path.display()
+            ),
+            link: Some(entry.url.clone()).filter(|url| url.starts_with("http")),
+            thumbnail: Some(entry.thumbnail.clone()).filter(|url| url.starts_with("http")),
+            failed: false,
+        };
+        for chat in self.chats() {
+            if chat.events.downloads() && chat.severity != Severity::Failures {
+                chat.send(&message);
+            }
+        }
+    }
+
+    // Posts a URL that failed to the chats that want per-download messages.
+    pub fn failed(&self, entry: &UrlEntry, class: ErrorClass, reason: &str) {
+        let message = Message {
+            title: format!("Failed ({}): {}", class.label(), entry.url),
+            text: format!("{}\n{}:{}", reason, entry.file.display(), entry.line),
+            link: None,
+            thumbnail: None,
+            failed: true,
+        };
+        for chat in self.chats() {
+            if chat.events.downloads() {
+                chat.send(&message);
+            }
+        }
+    }
+
+    // Sends the digest of a finished run to every notifier whose severity it meets. Failures
+    // to notify are reported but never fail the run.
+    pub fn run_finished(&self, report: &Report) {
+        if let Some(email) = &self.email {
+            if email.severity.applies_to(report) {
+                if let Err(err) = send_email(email, report) {
+                    say!(
+                        "Sending the run digest to {} failed: {}",
+                        email.to.join(", "),
+                        err
+                    );
+                }
+            }
+        }
+
+        let message = Message {
+            title: "dlyt run finished".to_string(),
+            text: report.summary(),
+            link: None,
+            thumbnail: None,
+            failed: report.failed() > 0,
+        };
+        for chat in self.chats() {
+            if chat.events.runs() && chat.severity.applies_to(report) {
+                chat.send(&message);
+            }
+        }
+    }
+}
+
+fn parse_severity(value: &str) -> Result<Severity, String> {
+    Severity::from_name(value).ok_or_else(|| {
+        format!(
+            "invalid severity `{}` (expected all, changes or failures)",
+            value
+        )
+    })
+}
+
+fn send_email(email: &Email, report: &Report) -> io::Result<()> {
+    let failed = report.failed();
+    let subject = if failed > 0 {
+        format!("dlyt: {} new files, {} failed", report.files.len(), failed)
+    } else {
+        format!("dlyt: {} new files", report.files.len())
+    };
+
+    let mut body = report.summary();
+    if !report.files.is_empty() {
+        body.push_str("\nNew files:\n");
+        for file in &report.files {
+            body.push_str(&format!(
+                "  {} [{}]\n    {}\n",
+                file.title,
+                human_size(file.size),
+                file.path.display()
+            ));
+        }
+    }
+
+    let mut message = format!(
+        "From: {}\r\nTo: {}\r\nSubject: {}\r\nDate: {}\r\nContent-Type: text/plain; charset=utf-8\r\n\r\n",
+        email.from,
+        email.to.join(", "),
+        subject,
+        date::format_rfc2822(date::now())
+    );
+    // SMTP wants CRLF line endings; curl escapes lines starting with `.` itself
+    for line in body.lines() {
+        message.push_str(line);
+        message.push_str("\r\n");
+    }
+
+    let message_file = std::env::temp_dir().join(format!("dlyt-{}-digest.eml", process::id()));
+    fs::write(&message_file, message)?;
+
+    let mut config = format!(
+        "silent\nshow-error\nssl-reqd\nurl = {}\nmail-from = {}\nupload-file = {}\n",
+        curl_quote(&email.server),
+        curl_quote(&email.from),
+        curl_quote(&message_file.to_string_lossy())
+    );
+    for to in &email.to {
+        config.push_str(&format!("mail-rcpt = {}\n", curl_quote(to)));
+    }
+    if let Some(username) = &email.username {
+        let password = email.password.as_deref().unwrap_or_default();
+        config.push_str(&format!(
+            "user = {}\n",
+            curl_quote(&format!("{}:{}", username, password))
+        ));
+    }
+
+    let result = curl(&config);
+    let _ = fs::remove_file(&message_file);
+    result
+}
+
+// Discord webhook post with an embed, green for downloads and red for failures.
+fn send_discord(webhook: &str, message: &Message) -> io::Result<()> {
+    let mut embed = format!(
+        "\"title\":{},\"description\":{},\"color\":{}",
+        json::quote(&truncate(&message.title, 256)),
+        json::quote(&truncate(&message.text, 4000)),
+        if message.failed {
+            0xd9_3f_3f
+        } else {
+            0x3f_a9_5b
+        }
+    );
+    if let Some(link) = &message.link {
+        embed.push_str(&format!(",\"url\":{}", json::quote(link)));
+    }
+    if let Some(thumbnail) = &message.thumbnail {
+        embed.push_str(&format!(
+            ",\"thumbnail\":{{\"url\":{}}}",
+            json::quote(thumbnail)
+        ));
+    }
+
+    curl(&format!(
+        "silent\nshow-error\nfail\nurl = {}\nheader = \"Content-Type: application/json\"\ndata = {}\n",
+        curl_quote(webhook),
+        curl_quote(&format!("{{\"embeds\":[{{{}}}]}}", embed))
+    ))
+}
+
+// Telegram bot message; a photo with caption when there is a thumbnail.
+fn send_telegram(token: &str, chat: &str, message: &Message) -> io::Result<()> {
+    let mut text = format!("{}\n{}", message.title, message.text);
+    if let Some(link) = &message.link {
+        text.push('\n');
+        text.push_str(link);
+    }
+
+    let mut config = String::from("silent\nshow-error\nfail\n");
+    config.push_str(&format!(
+        "data-urlencode = {}\n",
+        curl_quote(&format!("chat_id={}", chat))
+    ));
+    let method = match &message.thumbnail {
+        Some(thumbnail) => {
+            config.push_str(&format!(
+                "data-urlencode = {}\ndata-urlencode = {}\n",
+                curl_quote(&format!("photo={}", thumbnail)),
+                curl_quote(&format!("caption={}", truncate(&text, 1024)))
+            ));
+            "sendPhoto"
+        }
+        None => {
+            config.push_str(&format!(
+                "data-urlencode = {}\n",
+                curl_quote(&format!("text={}", truncate(&text, 4096)))
+            ));
+            "sendMessage"
+        }
+    };
+    config.push_str(&format!(
+        "url = {}\n",
+        curl_quote(&format!("https://api.telegram.org/bot{}/{}", token, method))
+    ));
+    curl(&config)
+}
+
+// Runs curl with options passed as a config on stdin, so tokens and passwords do not show up
+// in `ps`.
+fn curl(config: &str) -> io::Result<()> {
+    let mut child = Command::new("curl")
+        .args(["--config", "-"])
+        .stdin(Stdio::piped())
+        .stdout(Stdio::null())
+        .spawn()?;
+    if let Some(mut stdin) = child.stdin.take() {
+        stdin.write_all(config.as_bytes())?;
+    }
+    let status = child.wait()?;
+    if !status.success() {
+        return Err(io::Error::other(format!("curl exited with {}", status)));
+    }
+    Ok(())
+}
+
+// At most `max` characters of `text`.
+fn truncate(text: &str, max: usize) -> String {
+    match text.char_indices().nth(max) {
+        Some((index, _)) => text[..index].to_string(),
+        None => text.to_string(),
+    }
+}
+
+// A value in curl's config file syntax.
+fn curl_quote(value: &str) -> String {
+    let mut quoted = String::from("\"");
+    for c in value.chars() {
+        match c {
+            '\\' => quoted.push_str("\\\\"),
+            '"' => quoted.push_str("\\\""),
+            '\n' => quoted.push_str("\\n"),
+            '\r' => quoted.push_str("\\r"),
+            '\t' => quoted.push_str("\\t"),
+            c => quoted.push(c),
+        }
+    }
+    quoted.push('"');
+    quoted
+}
@@ -0,0 +1,190 @@
+use std::env;
+use std::fs;
+use std::io;
+use std::path::{self, Path, PathBuf};
+use std::process::Command;
+
+use crate::hash;
+use crate::{ARCHIVE_FILE, HISTORY_FILE};
+
+// Below the data directory: a state directory per root, see `state_dir`
+const ROOTS_DIR: &str = "roots";
+// Below the data directory: the yt-dlp `dlyt update` installs, shared by every root
+pub const BIN_DIR: &str = "bin";
+
+// Next to the executable, this file switches dlyt to portable mode: config and state are kept
+// in the executable's directory, e.g. on a USB stick, instead of the platform directories.
+const PORTABLE_MARKER: &str = "dlyt.portable";
+
+// Windows' limit for a whole path, unless long paths are enabled in the registry
+const WINDOWS_MAX_PATH: usize = 260;
+// Room left for what yt-dlp adds to a file name: `.f137`, `.webm.part`, `.en.vtt`, ...
+const SUFFIX_ROOM: usize = 32;
+
+// Where the config file lives by default, laid out like the `directories` crate does for an
+// application called dlyt: `~/.config/dlyt` on Linux, `~/Library/Application Support/dlyt` on
+// macOS and `%APPDATA%\dlyt\config` on Windows. In portable mode, the executable's directory.
+pub fn config_dir() -> Option<PathBuf> {
+    if let Some(dir) = portable_dir() {
+        Some(dir)
+    } else if cfg!(target_os = "windows") {
+        env_dir("APPDATA").map(|dir| dir.join("dlyt").join("config"))
+    } else if cfg!(target_os = "macos") {
+        mac_support_dir()
+    } else {
+        env_dir("XDG_CONFIG_HOME")
+            .or_else(|| home().map(|home| home.join(".config")))
+            .map(|dir| dir.join("dlyt"))
+    }
+}
+
+// Where the archive, history and other state go by default: `~/.local/share/dlyt` on Linux,
+// `~/Library/Application Support/dlyt` on macOS and `%APPDATA%\dlyt\data` on Windows. In
+// portable mode, `data` next to the executable.
+pub fn data_dir() -> Option<PathBuf> {
+    if let Some(dir) = portable_dir() {
+        Some(dir.join("data"))
+    } else if cfg!(target_os = "windows") {
+        env_dir("APPDATA").map(|dir| dir.join("dlyt").join("data"))
+    } else if cfg!(target_os = "macos") {
+        mac_support_dir()
+    } else {
+        env_dir("XDG_DATA_HOME")
+            .or_else(|| home().map(|home| home.join(".local").join("share")))
+            .map(|dir| dir.join("dlyt"))
+    }
+}
+
+// The state directory for a root: its own directory below `roots` in the data directory, as the
+// history and queues hold paths relative to the root. A root that already holds an archive or
+// history from before the platform directories were used keeps its state next to the urls
+// directory.
+pub fn state_dir(root: &Path) -> PathBuf {
+    if root.join(ARCHIVE_FILE).exists() || root.join(HISTORY_FILE).exists() {
+        return root.to_path_buf();
+    }
+    let Some(data) = data_dir() else {
+        return root.to_path_buf();
+    };
+    let root = root.canonicalize().unwrap_or_else(|_| root.to_path_buf());
+    // `videos-1f2e3d4c`: readable, and unique per path
+    let name = root
+        .file_name()
+        .map(|name| windows_safe(&name.to_string_lossy()))
+        .unwrap_or_else(|| "root".to_string());
+    let digest = hash::text_digest(&root.to_string_lossy());
+    data.join(ROOTS_DIR)
+        .join(format!("{}-{}", name, &digest[..8]))
+}
+
+// Moves the `files` of state kept directly in the data directory, where every root shared them
+// for a while, into the state directory of the first root run since. Returns whether it did.
+pub fn adopt_shared_state(state_dir: &Path, files: &[&str]) -> io::Result<bool> {
+    let Some(data) = data_dir() else {
+        return Ok(false);
+    };
+    let shared = data.join(ARCHIVE_FILE).exists() || data.join(HISTORY_FILE).exists();
+    if !shared || !state_dir.starts_with(data.join(ROOTS_DIR)) || state_dir.exists() {
+        return Ok(false);
+    }
+    fs::create_dir_all(state_dir)?;
+    // Not the managed yt-dlp, which stays shared, nor a config file in the same directory
+    for name in files {
+        let file = data.join(name);
+        if file.exists() {
+            fs::rename(file, state_dir.join(name))?;
+        }
+    }
+    Ok(true)
+}
+
+// The executable's directory when it holds PORTABLE_MARKER.
+pub fn portable_dir() -> Option<PathBuf> {
+    let exe = env::current_exe().ok()?;
+    let dir = exe.parent()?;
+    dir.join(PORTABLE_MARKER)
+        .is_file()
+        .then(|| dir.to_path_buf())
+}
+
+// Full path of a program on the PATH. On Windows this asks `where.exe`, which also finds the
+// `.cmd` and `.bat` shims pip and scoop install; Command only looks for `.exe` by itself.
+pub fn find_program(name: &str) -> Option<PathBuf> {
+    let output = if cfg!(target_os = "windows") {
+        Command::new("where.exe").arg(name).output()
+    } else {
+        Command::new("which").arg(name).output()
+    }
+    .ok()?;
+    if !output.status.success() {
+        return None;
+    }
+    let found = String::from_utf8_lossy(&output.stdout).into_owned();
+    let mut found = found.lines().map(str::trim).filter(|line| !line.is_empty());
+    if cfg!(target_os = "windows") {
+        // `where.exe` can also list a file without an extension, which Windows cannot run
+        found
+            .find(|line| {
+                Path::new(line)
+                    .extension()
+                    .and_then(|ext| ext.to_str())
+                    .is_some_and(|ext| {
+                        ["exe", "cmd", "bat", "com"].contains(&ext.to_lowercase().as_str())
+                    })
+            })
+            .map(PathBuf::from)
+    } else {
+        found.next().map(PathBuf::from)
+    }
+}
+
+// A file or directory name Windows can create: device names such as `CON` or `nul.txt` get an
+// `_` after their stem, and the trailing dots and spaces Windows drops are replaced.
+pub fn windows_safe(name: &str) -> String {
+    const RESERVED: &[&str] = &["CON", "PRN", "AUX", "NUL"];
+    let stem = name.split('.').next().unwrap_or_default().trim_end();
+    let upper = stem.to_ascii_uppercase();
+    let reserved = RESERVED.contains(&upper.as_str())
+        || ((upper.starts_with("COM") || upper.starts_with("LPT"))
+            && upper.len() == 4
+            && upper.as_bytes()[3].is_ascii_digit());
+    let safe = if reserved {
+        format!("{}_{}", stem, &name[stem.len()..])
+    } else {
+        name.to_string()
+    };
+    let trimmed = safe.trim_end_matches(['.', ' ']);
+    if trimmed.len() < safe.len() {
+        format!("{}_", trimmed)
+    } else {
+        safe
+    }
+}
+
+// The longest file name, without its extension, that keeps a file in `dir` under Windows' path
+// limit, for yt-dlp's `--trim-filenames`.
+pub fn name_limit(dir: &Path) -> usize {
+    let dir_len = path::absolute(dir).map_or(dir.as_os_str().len(), |dir| dir.as_os_str().len());
+    WINDOWS_MAX_PATH
+        .saturating_sub(dir_len + 1 + SUFFIX_ROOM)
+        .max(SUFFIX_ROOM)
+}
+
+fn mac_support_dir() -> Option<PathBuf> {
+    home().map(|home| {
+        home.join("Library")
+            .join("Application Support")
+            .join("dlyt")
+    })
+}
+
+fn home() -> Option<PathBuf> {
+    env_dir("HOME").or_else(|| env_dir("USERPROFILE"))
+}
+
+// An absolute directory from an environment variable; the XDG spec says to ignore relative ones.
+fn env_dir(name: &str) -> Option<PathBuf> {
+    env::var_os(name)
+        .map(PathBuf::from)
+        .filter(|dir| dir.is_absolute())
+}
@@ -0,0 +1,93 @@
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use crate::manifest::{self, Item};
+use crate::INDEX_FILE;
+
+// Order of the videos in a collection's .m3u8 playlist, from `--playlist-order`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PlaylistOrder {
+    // Oldest upload first; videos without an upload date by download date after them
+    Upload,
+    // Oldest download first
+    Download,
+}
+
+impl PlaylistOrder {
+    pub fn from_name(name: &str) -> Option<PlaylistOrder> {
+        match name {
+            "upload" => Some(PlaylistOrder::Upload),
+            "download" => Some(PlaylistOrder::Download),
+            _ => None,
+        }
+    }
+}
+
+// Writes `<dir>/<dir name>.m3u8` from the directory's index.json, with paths relative to it
+// so the collection can be moved or shared as a whole. None when there is no index.
+pub fn write(dir: &Path, order: PlaylistOrder) -> io::Result<Option<PathBuf>> {
+    let mut items: Vec<Item> = manifest::load(dir)?
+        .into_iter()
+        .filter(|item| dir.join(&item.file).is_file())
+        .collect();
+    if items.is_empty() {
+        return Ok(None);
+    }
+    match order {
+        PlaylistOrder::Upload => items.sort_by(|a, b| {
+            (a.uploaded.is_none(), &a.uploaded, &a.downloaded).cmp(&(
+                b.uploaded.is_none(),
+                &b.uploaded,
+                &b.downloaded,
+            ))
+        }),
+        PlaylistOrder::Download => items.sort_by(|a, b| a.downloaded.cmp(&b.downloaded)),
+    }
+
+    let mut contents = String::from("#EXTM3U\n");
+    for item in &items {
+        contents.push_str(&format!(
+            "#EXTINF:{},{}\n{}\n",
+            item.duration.map_or(-1, |d| d.round() as i64),
+            item.title.replace(['\n', '\r'], " "),
+            // Lines starting with `#` would be read as comments
+            if item.file.starts_with('#') {
+                format!("./{}", item.file)
+            } else {
+                item.file.clone()
+            }
+        ));
+    }
+
+    let name = fs::canonicalize(dir)
+        .ok()
+        .and_then(|dir| dir.file_name().map(|n| n.to_string_lossy().to_string()))
+        .unwrap_or_else(|| "playlist".to_string());
+    let path = dir.join(format!("{}.m3u8", name));
+    fs::write(&path, contents)?;
+    Ok(Some(path))
+}
+
+// Writes the playlist of every directory below `root`, itself included, that has an
+// index.json. Returns how many were written.
+pub fn write_all(root: &Path, order: PlaylistOrder) -> io::Result<usize> {
+    let mut dirs = vec![root.to_path_buf()];
+    let mut written = 0;
+    while let Some(dir) = dirs.pop() {
+        for entry in fs::read_dir(&dir)? {
+            let path = entry?.path();
+            if path.is_dir() {
+                dirs.push(path);
+            }
+        }
+        if !dir.join(INDEX_FILE).is_file() {
+            continue;
+        }
+        if let Some(path) = write(&dir, order)? {
+            println!("Wrote {}", path.display());
+            written += 1;
+        }
+    }
+    Ok(written)
+}
@@ -0,0 +1,204 @@
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use crate::{container, ORIGINALS_DIR};
+
+// EBU R128 targets: integrated loudness, true peak and loudness range.
+const LOUDNORM_TARGET: &str = "I=-16:TP=-1.5:LRA=11";
+
+// Two-pass loudnorm: the first pass measures the first audio track, the second pass applies
+// the measured values linearly to every audio track while copying all other streams.
+pub fn normalize_audio(path: &Path) -> io::Result<()> {
+    let output = container::command("ffmpeg")
+        .args(["-hide_banner", "-nostdin", "-i"])
+        .arg(path)
+        .args(["-map", "0:a:0", "-af"])
+        .arg(format!("loudnorm={}:print_format=json", LOUDNORM_TARGET))
+        .args(["-f", "null", "-"])
+        .output()?;
+    if !output.status.success() {
+        return Err(io::Error::other(format!(
+            "ffmpeg could not measure the loudness of {}",
+            path.display()
+        )));
+    }
+
+    let stats = String::from_utf8_lossy(&output.stderr);
+    let measured = |key: &str| {
+        loudnorm_value(&stats, key).ok_or_else(|| {
+            io::Error::other(format!(
+                "ffmpeg did not report {} for {}",
+                key,
+                path.display()
+            ))
+        })
+    };
+    let filter = format!(
+        "loudnorm={}:measured_I={}:measured_TP={}:measured_LRA={}:measured_thresh={}:offset={}:linear=true",
+        LOUDNORM_TARGET,
+        measured("input_i")?,
+        measured("input_tp")?,
+        measured("input_lra")?,
+        measured("input_thresh")?,
+        measured("target_offset")?,
+    );
+
+    let temp = sibling_with_suffix(path, "loudnorm");
+    let status = container::command("ffmpeg")
+        .args(["-hide_banner", "-nostdin", "-loglevel", "error", "-y", "-i"])
+        .arg(path)
+        .args([
+            "-map",
+            "0",
+            "-c",
+            "copy",
+            "-c:a",
+            audio_codec_for(path),
+            "-af",
+        ])
+        .arg(filter)
+        .args(["-ar", "48000"])
+        .arg(&temp)
+        .status()?;
+    if !status.success() {
+        let _ = fs::remove_file(&temp);
+        return Err(io::Error::other(format!(
+            "ffmpeg failed to normalize {}",
+            path.display()
+        )));
+    }
+
+    fs::rename(&temp, path)
+}
+
+// Puts the untouched download into an `originals` directory next to it before a stage
+// rewrites it. A hard link costs no space until the stage replaces the file.
+pub fn keep_original(path: &Path) -> io::Result<PathBuf> {
+    let dir = path
+        .parent()
+        .unwrap_or_else(|| Path::new(""))
+        .join(ORIGINALS_DIR);
+    fs::create_dir_all(&dir)?;
+    let original = dir.join(path.file_name().unwrap_or_default());
+    let _ = fs::remove_file(&original);
+    if fs::hard_link(path, &original).is_err() {
+        fs::copy(path, &original)?;
+    }
+    Ok(original)
+}
+
+// Hardcodes the subtitle track in `language` (`en`, or `eng` as stored in the file) into the
+// video, re-encoding it, and writes the result to `output`, which may be `path` itself.
+pub fn burn_subtitles(path: &Path, language: &str, output: &Path) -> io::Result<()> {
+    let tracks = subtitle_languages(path)?;
+    let index = tracks
+        .iter()
+        .position(|track| {
+            track
+                .as_deref()
+                .is_some_and(|track| track == language || track.starts_with(language))
+        })
+        .ok_or_else(|| {
+            io::Error::other(format!(
+                "{} has no {} subtitles to burn in",
+                path.display(),
+                language
+            ))
+        })?;
+
+    // The subtitles filter takes a file name inside the filter graph, where quotes, colons
+    // and backslashes in titles would need escaping; run it on a link with a plain name.
+    let dir = match path.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir,
+        _ => Path::new("."),
+    };
+    let input = format!(".dlyt-burn.{}", extension(path));
+    let _ = fs::remove_file(dir.join(&input));
+    if fs::hard_link(path, dir.join(&input)).is_err() {
+        fs::copy(path, dir.join(&input))?;
+    }
+    let temp = sibling_with_suffix(path, "burn");
+
+    let mut command = container::command("ffmpeg");
+    command
+        .current_dir(dir)
+        .args(["-hide_banner", "-nostdin", "-loglevel", "error", "-y", "-i"])
+        .arg(&input)
+        .args([
+            "-map", "0:V:0", "-map", "0:a?", "-map", "0:s?", "-map", "0:t?",
+        ])
+        .arg("-vf")
+        .arg(format!("subtitles={}:si={}", input, index))
+        .args(["-c", "copy"]);
+    match extension(path).as_str() {
+        "webm" => command.args(["-c:v", "libvpx-vp9", "-b:v", "0", "-crf", "31"]),
+        _ => command.args(["-c:v", "libx264", "-crf", "18", "-preset", "medium"]),
+    };
+    let status = command.arg(temp.file_name().unwrap_or_default()).status();
+    let _ = fs::remove_file(dir.join(&input));
+    if !status?.success() {
+        let _ = fs::remove_file(&temp);
+        return Err(io::Error::other(format!(
+            "ffmpeg failed to burn subtitles into {}",
+            path.display()
+        )));
+    }
+    fs::rename(&temp, output)
+}
+
+// Language of each subtitle stream, in order, as ffmpeg describes the file.
+fn subtitle_languages(path: &Path) -> io::Result<Vec<Option<String>>> {
+    let output = container::command("ffmpeg")
+        .args(["-hide_banner", "-nostdin", "-i"])
+        .arg(path)
+        .output()?;
+    // `  Stream #0:2(eng): Subtitle: ass`, or `Stream #0:2[0x3](eng): ...` in newer versions
+    Ok(String::from_utf8_lossy(&output.stderr)
+        .lines()
+        .filter(|line| line.trim_start().starts_with("Stream #"))
+        .filter_map(|line| line.split_once(": Subtitle:").map(|(head, _)| head))
+        .map(|head| {
+            let start = head.rfind('(')?;
+            let end = head[start..].find(')')? + start;
+            Some(head[start + 1..end].to_string())
+        })
+        .collect())
+}
+
+// Extracts a value such as `"input_i" : "-23.51"` from the JSON block loudnorm prints.
+fn loudnorm_value(stats: &str, key: &str) -> Option<String> {
+    let start = stats.rfind(&format!("\"{}\"", key))?;
+    let rest = &stats[start + key.len() + 2..];
+    let rest = &rest[rest.find(':')? + 1..];
+    let rest = &rest[rest.find('"')? + 1..];
+    Some(rest[..rest.find('"')?].to_string())
+}
+
+fn audio_codec_for(path: &Path) -> &'static str {
+    match extension(path).as_str() {
+        "webm" | "opus" | "ogg" => "libopus",
+        "mp3" => "libmp3lame",
+        "flac" => "flac",
+        _ => "aac",
+    }
+}
+
+fn extension(path: &Path) -> String {
+    path.extension()
+        .and_then(|ext| ext.to_str())
+        .unwrap_or_default()
+        .to_ascii_lowercase()
+}
+
+// `video.mkv` -> `video.<suffix>.mkv`, so ffmpeg still infers the container from the extension.
+pub fn sibling_with_suffix(path: &Path, suffix: &str) -> PathBuf {
+    let stem = path
+        .file_stem()
+        .and_then(|s| s.to_str())
+        .unwrap_or_default();
+    match path.extension().and_then(|ext| ext.to_str()) {
+        Some(ext) => path.with_file_name(format!("{}.{}.{}", stem, suffix, ext)),
+        None => path.with_file_name(format!("{}.{}", stem, suffix)),
+    }
+}
@@ -0,0 +1,229 @@
+use std::collections::BTreeMap;
+use std::io::Read;
+use std::process::Stdio;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::mpsc;
+use std::thread;
+
+use crate::archive;
+use crate::cli::Options;
+use crate::json::{self, Value};
+use crate::urls::UrlEntry;
+use crate::ytdlp;
+
+// What `yt-dlp -J` reports about a URL before it is downloaded.
+#[derive(Debug, Clone, Default)]
+pub struct Probe {
+    pub title: Option<String>,
+    // The video's key in the download archive, `<extractor> <id>`
+    pub archive_id: Option<String>,
+    pub duration: Option<f64>,
+    // YYYYMMDD
+    pub upload_date: Option<String>,
+    pub formats: Vec<Format>,
+    // The videos of a playlist or channel, listed without probing each
+    pub entries: Option<Vec<PlaylistEntry>>,
+}
+
+#[derive(Debug, Clone)]
+pub struct Format {
+    pub height: Option<u32>,
+    pub video: bool,
+    pub audio: bool,
+    // Audio language, e.g. `en-US` for a dubbed track
+    pub language: Option<String>,
+    // Bytes, exact or as estimated by the site
+    pub size: Option<u64>,
+}
+
+#[derive(Debug, Clone)]
+pub struct PlaylistEntry {
+    pub url: String,
+    // The video's key in the download archive, `<extractor> <id>`
+    pub archive_id: Option<String>,
+}
+
+impl Probe {
+    fn from_json(info: &Value) -> Probe {
+        let formats = info
+            .get("formats")
+            .map(Value::as_array)
+            .unwrap_or_default()
+            .iter()
+            .map(|format| Format {
+                height: format
+                    .get("height")
+                    .and_then(Value::as_f64)
+                    .map(|h| h as u32),
+                video: format.get("vcodec").and_then(Value::as_str) != Some("none"),
+                audio: format.get("acodec").and_then(Value::as_str) != Some("none"),
+                language: format
+                    .get("language")
+                    .and_then(Value::as_str)
+                    .map(str::to_string),
+                size: ["filesize", "filesize_approx"]
+                    .into_iter()
+                    .find_map(|key| format.get(key).and_then(Value::as_f64))
+                    .map(|size| size as u64),
+            })
+            .collect();
+        let entries = match info.get("_type").and_then(Value::as_str) {
+            Some("playlist" | "multi_video") => Some(
+                info.get("entries")
+                    .map(Value::as_array)
+                    .unwrap_or_default()
+                    .iter()
+                    .filter_map(PlaylistEntry::from_json)
+                    .collect(),
+            ),
+            _ => None,
+        };
+        let field = |key| info.get(key).and_then(Value::as_str);
+        Probe {
+            title: field("title").map(str::to_string),
+            archive_id: match (field("extractor_key"), field("id")) {
+                (Some(extractor), Some(id)) => Some(format!("{} {}", extractor.to_lowercase(), id)),
+                _ => None,
+            },
+            duration: info.get("duration").and_then(Value::as_f64),
+            upload_date: field("upload_date").map(str::to_string),
+            formats,
+            entries,
+        }
+    }
+}
+
+impl PlaylistEntry {
+    fn from_json(entry: &Value) -> Option<PlaylistEntry> {
+        let field = |key| entry.get(key).and_then(Value::as_str);
+        // Some extractors list bare ids as `url`
+        let url = [field("url"), field("webpage_url")]
+            .into_iter()
+            .flatten()
+            .find(|url| url.contains("://"))?;
+        let archive_id = match (field("ie_key"), field("id")) {
+            (Some(extractor), Some(id)) => Some(format!("{} {}", extractor.to_lowercase(), id)),
+            _ => None,
+        };
+        Some(PlaylistEntry {
+            url: url.to_string(),
+            archive_id,
+        })
+    }
+}
+
+// Runs `yt-dlp -J` for one URL. Playlists are listed flat, one cheap call for all entries,
+// and only the `items=` selected.
+pub fn probe(opts: &Options, entry: &UrlEntry) -> Result<Probe, String> {
+    let mut command = ytdlp::command();
+    command.arg("-J").arg("--flat-playlist");
+    // Like the download, a URL that resolved to one video ignores its `list=` parameter
+    if archive::canonical_id(&entry.url).is_some() {
+        command.arg("--no-playlist");
+    }
+    if let Some(items) = &entry.directives.items {
+        command.arg("--playlist-items").arg(items);
+    }
+    if let Some(proxy) = &opts.proxy {
+        command.arg("--proxy").arg(proxy);
+    }
+    let mut child = command
+        .arg("--")
+        .arg(&entry.url)
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .map_err(|err| format!("running yt-dlp failed: {}", err))?;
+
+    // Read stderr on the side so a chatty yt-dlp cannot block on a full pipe
+    let mut stderr = child.stderr.take();
+    let errors = thread::spawn(move || {
+        let mut text = String::new();
+        if let Some(stderr) = &mut stderr {
+            let _ = stderr.read_to_string(&mut text);
+        }
+        text
+    });
+    let mut stdout = String::new();
+    if let Some(out) = &mut child.stdout {
+        out.read_to_string(&mut stdout)
+            .map_err(|err| format!("reading yt-dlp's output failed: {}", err))?;
+    }
+    let status = child
+        .wait()
+        .map_err(|err| format!("waiting for yt-dlp failed: {}", err))?;
+    let errors = errors.join().unwrap_or_default();
+
+    if !status.success() {
+        return Err(errors
+            .lines()
+            .rev()
+            .find(|line| line.starts_with("ERROR:"))
+            .map(|line| line.trim_start_matches("ERROR:").trim().to_string())
+            .unwrap_or_else(|| format!("yt-dlp exited with {}", status)));
+    }
+    json::parse(&stdout)
+        .map(|info| Probe::from_json(&info))
+        .map_err(|err| format!("unreadable yt-dlp output: {}", err))
+}
+
+// Probes the given entries on up to `jobs` threads and hands each result to `each` in list
+// order as soon as it and every entry before it are done, so downloads start while later URLs
+// are still being probed. None entries are not probed and passed on as None. Stops when `each`
+// returns false.
+pub fn probe_in_order<E>(
+    opts: &Options,
+    urls: &[Option<&UrlEntry>],
+    jobs: usize,
+    mut each: impl FnMut(usize, Option<Result<Probe, String>>) -> Result<bool, E>,
+) -> Result<(), E> {
+    let next = AtomicUsize::new(0);
+    let stop = AtomicBool::new(false);
+
+    thread::scope(|scope| {
+        let (results, done) = mpsc::channel();
+        for _ in 0..jobs.clamp(1, urls.len().max(1)) {
+            let results = results.clone();
+            let (next, stop) = (&next, &stop);
+            scope.spawn(move || {
+                while !stop.load(Ordering::Relaxed) {
+                    let index = next.fetch_add(1, Ordering::Relaxed);
+                    let Some(url) = urls.get(index) else {
+                        break;
+                    };
+                    let result = url.map(|entry| probe(opts, entry));
+                    if results.send((index, result)).is_err() {
+                        break;
+                    }
+                }
+            });
+        }
+        drop(results);
+
+        let mut finished = BTreeMap::new();
+        for index in 0..urls.len() {
+            while !finished.contains_key(&index) {
+                match done.recv() {
+                    Ok((i, result)) => {
+                        finished.insert(i, result);
+                    }
+                    Err(_) => break,
+                }
+            }
+            let result = finished.remove(&index).flatten();
+            match each(index, result) {
+                Ok(true) => {}
+                Ok(false) => {
+                    stop.store(true, Ordering::Relaxed);
+                    break;
+                }
+                Err(err) => {
+                    stop.store(true, Ordering::Relaxed);
+                    return Err(err);
+                }
+            }
+        }
+        Ok(())
+    })
+}
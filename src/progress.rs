@@ -0,0 +1,174 @@
+use std::io::{self, Write};
+
+use crate::json;
+use crate::units;
+
+// One `--progress-json` event: a JSON object on its own line of stdout.
+#[derive(Debug, Default)]
+pub struct Event<'a> {
+    // download, postprocess, deliver or done
+    pub phase: &'a str,
+    pub url: &'a str,
+    // Archive key of the video, when known
+    pub id: Option<&'a str>,
+    pub percent: Option<f64>,
+    // Bytes per second
+    pub speed: Option<f64>,
+    // Seconds left
+    pub eta: Option<u64>,
+    // For `done`: downloaded, skipped or failed
+    pub status: Option<&'a str>,
+    pub message: Option<&'a str>,
+}
+
+impl Event<'_> {
+    pub fn emit(&self) {
+        let mut fields = vec![
+            format!("\"phase\":{}", json::quote(self.phase)),
+            format!("\"url\":{}", json::quote(self.url)),
+            format!("\"id\":{}", self.id.map_or("null".to_string(), json::quote)),
+        ];
+        if let Some(percent) = self.percent {
+            fields.push(format!("\"percent\":{:.1}", percent));
+        }
+        if let Some(speed) = self.speed {
+            fields.push(format!("\"speed\":{:.0}", speed));
+        }
+        if let Some(eta) = self.eta {
+            fields.push(format!("\"eta\":{}", eta));
+        }
+        if let Some(status) = self.status {
+            fields.push(format!("\"status\":{}", json::quote(status)));
+        }
+        if let Some(message) = self.message {
+            fields.push(format!("\"message\":{}", json::quote(message)));
+        }
+        // Whole lines only, even with the probe and download threads writing too
+        let mut stdout = io::stdout().lock();
+        let _ = writeln!(stdout, "{{{}}}", fields.join(","));
+        let _ = stdout.flush();
+    }
+}
+
+// Marks the lines `TEMPLATE` makes yt-dlp print, among its other output.
+const MARKER: &str = "[dlyt-progress]";
+
+// yt-dlp `--progress-template`: the same fields whichever downloader is in use. Missing ones
+// come out as `NA`.
+pub const TEMPLATE: &str = "download:[dlyt-progress] %(progress.status)s \
+    %(progress.downloaded_bytes)s %(progress.total_bytes)s %(progress.total_bytes_estimate)s \
+    %(progress.speed)s %(progress.eta)s %(progress.elapsed)s";
+
+// Below this many bytes per second a download counts as slow.
+const SLOW_SPEED: f64 = 100.0 * 1024.0;
+// A failed download that was slow for this long was most likely throttled.
+const THROTTLED_AFTER: f64 = 60.0;
+
+// One progress line of yt-dlp.
+#[derive(Debug, Default)]
+pub struct Update {
+    // downloading, finished or error
+    pub status: String,
+    pub downloaded: Option<u64>,
+    // Exact or estimated size
+    pub total: Option<u64>,
+    // Bytes per second
+    pub speed: Option<f64>,
+    // Seconds left
+    pub eta: Option<u64>,
+    // Seconds since this file started
+    pub elapsed: Option<f64>,
+}
+
+impl Update {
+    // None for any other line of yt-dlp's output.
+    pub fn parse(line: &str) -> Option<Update> {
+        let mut fields = line.strip_prefix(MARKER)?.split_whitespace();
+        let status = fields.next()?.to_string();
+        let mut number = || fields.next().and_then(|f| f.parse::<f64>().ok());
+        let (downloaded, total, estimate, speed, eta, elapsed) =
+            (number(), number(), number(), number(), number(), number());
+        Some(Update {
+            status,
+            downloaded: downloaded.map(|n| n as u64),
+            total: total.or(estimate).map(|n| n as u64),
+            speed,
+            eta: eta.map(|n| n as u64),
+            elapsed,
+        })
+    }
+
+    pub fn percent(&self) -> Option<f64> {
+        match (self.downloaded, self.total) {
+            (Some(downloaded), Some(total)) if total > 0 => {
+                Some((downloaded as f64 * 100.0 / total as f64).min(100.0))
+            }
+            _ if self.status == "finished" => Some(100.0),
+            _ => None,
+        }
+    }
+
+    // `[=========           ]  45.2% of 12.3 MiB at 1.2 MiB/s, 5s left`
+    pub fn display(&self) -> String {
+        let percent = self.percent();
+        let width = 20;
+        let filled = percent.map_or(0, |p| (p / 100.0 * width as f64) as usize);
+        let mut line = format!(
+            "[{}{}] {}",
+            "=".repeat(filled),
+            " ".repeat(width - filled),
+            percent.map_or_else(|| "   ?".to_string(), |p| format!("{:5.1}%", p))
+        );
+        if let Some(total) = self.total {
+            line.push_str(&format!(" of {}", units::human_size(total)));
+        }
+        if let Some(speed) = self.speed {
+            line.push_str(&format!(" at {}/s", units::human_size(speed as u64)));
+        }
+        if let Some(eta) = self.eta.filter(|_| self.status == "downloading") {
+            line.push_str(&format!(", {} left", units::human_duration(eta)));
+        }
+        if let Some(elapsed) = self.elapsed.filter(|_| self.status == "finished") {
+            line.push_str(&format!(" in {}", units::human_duration(elapsed as u64)));
+        }
+        line
+    }
+}
+
+// What the progress of one yt-dlp run adds up to.
+#[derive(Debug, Default)]
+pub struct Tracker {
+    // Time spent transferring the finished files, without extraction and merging
+    pub transfer_seconds: f64,
+    // How long the speed has stayed below SLOW_SPEED, as of the last update
+    slow_for: f64,
+    slow_since: Option<f64>,
+}
+
+impl Tracker {
+    pub fn update(&mut self, update: &Update) {
+        match update.status.as_str() {
+            "finished" => {
+                self.transfer_seconds += update.elapsed.unwrap_or(0.0);
+                self.slow_since = None;
+            }
+            "downloading" => {
+                let (Some(speed), Some(elapsed)) = (update.speed, update.elapsed) else {
+                    return;
+                };
+                if speed < SLOW_SPEED {
+                    let since = *self.slow_since.get_or_insert(elapsed);
+                    self.slow_for = elapsed - since;
+                } else {
+                    self.slow_since = None;
+                    self.slow_for = 0.0;
+                }
+            }
+            _ => {}
+        }
+    }
+
+    pub fn throttled(&self) -> bool {
+        self.slow_for >= THROTTLED_AFTER
+    }
+}
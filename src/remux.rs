@@ -0,0 +1,297 @@
+use std::collections::HashMap;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use crate::json::{self, Value};
+use crate::{clean, container, deliver, hash, history, ORIGINALS_DIR};
+
+// Containers `dlyt remux --to` converts into.
+pub const CONTAINERS: &[&str] = &["mkv", "mp4", "webm"];
+
+const VIDEO_EXTENSIONS: &[&str] = &["mkv", "mp4", "webm", "m4v", "mov"];
+const SUBTITLE_EXTENSIONS: &[&str] = &["vtt", "srt", "ass"];
+
+// What a file needs: its new container, and the sidecars to embed.
+struct Plan {
+    target: PathBuf,
+    subtitles: Vec<PathBuf>,
+    chapters: Option<String>,
+}
+
+// Brings already downloaded files below `dir` up to date without downloading anything:
+// converts them into the `to` container and embeds subtitles and chapters from sidecars when
+// the file lacks them. Only the files the history records are touched, so the untouched
+// downloads --keep-original keeps and sidecar videos such as burned-in copies stay as they
+// are. Paths and sizes in the history follow. Returns false if a file failed.
+pub fn remux(dir: &Path, to: Option<&str>, history_file: &Path) -> io::Result<bool> {
+    let mut history = history::load(history_file)?;
+    let recorded: HashMap<PathBuf, Vec<usize>> =
+        history
+            .iter()
+            .enumerate()
+            .fold(HashMap::new(), |mut recorded, (index, entry)| {
+                if let Ok(path) = entry.path.canonicalize() {
+                    recorded.entry(path).or_default().push(index);
+                }
+                recorded
+            });
+
+    let (mut converted, mut unchanged, mut failed, mut unrecorded) = (0, 0, 0, 0);
+    for file in clean::files_below(dir)? {
+        let ext = extension(&file);
+        if !VIDEO_EXTENSIONS.contains(&ext.as_str())
+            || clean::is_leftover(&file)
+            || file
+                .components()
+                .any(|part| part.as_os_str() == ORIGINALS_DIR)
+        {
+            continue;
+        }
+        let recorded_as = file.canonicalize().ok();
+        let Some(entries) = recorded_as.and_then(|path| recorded.get(&path)) else {
+            unrecorded += 1;
+            continue;
+        };
+        let Some(plan) = plan(&file, to) else {
+            unchanged += 1;
+            continue;
+        };
+        if plan.target != file && plan.target.exists() {
+            println!(
+                "Skipping {}: {} already exists.",
+                file.display(),
+                plan.target.display()
+            );
+            failed += 1;
+            continue;
+        }
+
+        println!("Remuxing {}", file.display());
+        if let Err(err) = convert(&file, &plan) {
+            println!("Remuxing {} failed: {}", file.display(), err);
+            failed += 1;
+            continue;
+        }
+        converted += 1;
+
+        let size = fs::metadata(&plan.target).map_or(0, |m| m.len());
+        // Digests recorded with --hash follow the new contents, so duplicates are still found
+        let digest = entries
+            .iter()
+            .any(|&index| history[index].hash.is_some())
+            .then(|| hash::file_digest(&plan.target).ok())
+            .flatten();
+        for &index in entries {
+            let entry = &mut history[index];
+            entry.path = entry.path.with_extension(extension(&plan.target));
+            entry.size = size;
+            if entry.hash.is_some() {
+                entry.hash = digest.clone();
+            }
+        }
+    }
+
+    if converted > 0 {
+        history::save(history_file, &history)?;
+    }
+    println!(
+        "Remuxed {} file(s), {} already up to date, {} failed.",
+        converted, unchanged, failed
+    );
+    if unrecorded > 0 {
+        println!(
+            "Left {} video file(s) alone that are not in the download history.",
+            unrecorded
+        );
+    }
+    Ok(failed == 0)
+}
+
+// None when the file already is in the wanted container and has its subtitles and chapters.
+fn plan(file: &Path, to: Option<&str>) -> Option<Plan> {
+    let ext = extension(file);
+    let target = match to {
+        Some(to) if to != ext => file.with_extension(to),
+        _ => file.to_path_buf(),
+    };
+
+    let (embedded_subtitles, has_chapters) = streams(file);
+    let sidecars = deliver::sidecars(file);
+    let subtitles: Vec<PathBuf> = sidecars
+        .iter()
+        .filter(|sidecar| SUBTITLE_EXTENSIONS.contains(&extension(sidecar).as_str()))
+        .cloned()
+        .collect();
+    let subtitles = if subtitles.len() > embedded_subtitles {
+        subtitles
+    } else {
+        Vec::new()
+    };
+    let chapters = if has_chapters {
+        None
+    } else {
+        sidecars
+            .iter()
+            .find(|sidecar| sidecar.to_string_lossy().ends_with(".info.json"))
+            .and_then(|info| ffmetadata_chapters(info))
+    };
+
+    if target == file && subtitles.is_empty() && chapters.is_none() {
+        return None;
+    }
+    Some(Plan {
+        target,
+        subtitles,
+        chapters,
+    })
+}
+
+// Number of subtitle streams and whether there are chapters, from ffmpeg's description of
+// the input.
+fn streams(file: &Path) -> (usize, bool) {
+    let Ok(output) = container::command("ffmpeg")
+        .args(["-hide_banner", "-nostdin", "-i"])
+        .arg(file)
+        .output()
+    else {
+        return (0, false);
+    };
+    let description = String::from_utf8_lossy(&output.stderr);
+    let subtitles = description
+        .lines()
+        .filter(|line| line.trim_start().starts_with("Stream #") && line.contains("Subtitle:"))
+        .count();
+    let chapters = description.lines().any(|line| line.trim() == "Chapters:");
+    (subtitles, chapters)
+}
+
+// Chapters from a yt-dlp .info.json sidecar as an ffmetadata document.
+fn ffmetadata_chapters(info_json: &Path) -> Option<String> {
+    let info = json::parse(&fs::read_to_string(info_json).ok()?).ok()?;
+    let chapters = info.get("chapters")?.as_array();
+    if chapters.is_empty() {
+        return None;
+    }
+
+    let mut out = String::from(";FFMETADATA1\n");
+    for chapter in chapters {
+        let time = |key| chapter.get(key).and_then(Value::as_f64);
+        let (Some(start), Some(end)) = (time("start_time"), time("end_time")) else {
+            continue;
+        };
+        let title = chapter.get("title").and_then(Value::as_str).unwrap_or("");
+        out.push_str(&format!(
+            "[CHAPTER]\nTIMEBASE=1/1000\nSTART={}\nEND={}\ntitle={}\n",
+            (start * 1000.0) as u64,
+            (end * 1000.0) as u64,
+            escape_ffmetadata(title)
+        ));
+    }
+    Some(out)
+}
+
+fn escape_ffmetadata(value: &str) -> String {
+    let mut out = String::new();
+    for c in value.chars() {
+        if matches!(c, '=' | ';' | '#' | '\\' | '\n') {
+            out.push('\\');
+        }
+        out.push(c);
+    }
+    out
+}
+
+// Copies every stream into the target container next to the file, adds the sidecars, and
+// replaces the file with the result.
+fn convert(file: &Path, plan: &Plan) -> io::Result<()> {
+    let target_ext = extension(&plan.target);
+    let temp = plan.target.with_extension(format!("remux.{}", target_ext));
+    let chapters_file = plan
+        .chapters
+        .as_ref()
+        .map(|chapters| -> io::Result<PathBuf> {
+            // Not `Title.chapters.txt`, which is the chapter list --twitch-chapters writes
+            let path = file.with_extension("remux-chapters.ffmeta");
+            fs::write(&path, chapters)?;
+            Ok(path)
+        })
+        .transpose()?;
+
+    let mut command = container::command("ffmpeg");
+    command
+        .args(["-hide_banner", "-nostdin", "-loglevel", "error", "-y", "-i"])
+        .arg(file);
+    for subtitle in &plan.subtitles {
+        command.arg("-i").arg(subtitle);
+    }
+    if let Some(chapters_file) = &chapters_file {
+        command.args(["-f", "ffmetadata", "-i"]).arg(chapters_file);
+    }
+    // Attachments such as mkv cover art do not fit every container
+    command.args(["-map", "0:v?", "-map", "0:a?"]);
+    if plan.subtitles.is_empty() {
+        command.args(["-map", "0:s?"]);
+    }
+    for (index, subtitle) in plan.subtitles.iter().enumerate() {
+        command.arg("-map").arg(format!("{}:s", index + 1));
+        if let Some(language) = subtitle_language(file, subtitle) {
+            command
+                .arg(format!("-metadata:s:s:{}", index))
+                .arg(format!("language={}", language));
+        }
+    }
+    if chapters_file.is_some() {
+        command
+            .arg("-map_chapters")
+            .arg((plan.subtitles.len() + 1).to_string());
+    }
+    let status = command
+        .args([
+            "-c",
+            "copy",
+            "-c:s",
+            subtitle_codec(&extension(file), &target_ext),
+        ])
+        .arg(&temp)
+        .status();
+    if let Some(chapters_file) = &chapters_file {
+        let _ = fs::remove_file(chapters_file);
+    }
+    if !status?.success() {
+        let _ = fs::remove_file(&temp);
+        return Err(io::Error::other("ffmpeg failed"));
+    }
+
+    fs::rename(&temp, &plan.target)?;
+    if plan.target != file {
+        fs::remove_file(file)?;
+    }
+    Ok(())
+}
+
+// Subtitle codec the target container takes.
+fn subtitle_codec(source_ext: &str, target_ext: &str) -> &'static str {
+    match target_ext {
+        "mp4" | "m4v" | "mov" => "mov_text",
+        "webm" => "webvtt",
+        // mkv cannot hold the mp4 subtitle codec
+        _ if matches!(source_ext, "mp4" | "m4v" | "mov") => "srt",
+        _ => "copy",
+    }
+}
+
+// `video.en.vtt` next to `video.mkv` -> `en`.
+fn subtitle_language(file: &Path, subtitle: &Path) -> Option<String> {
+    let stem = file.file_stem()?.to_str()?;
+    let name = subtitle.file_stem()?.to_str()?;
+    let language = name.strip_prefix(stem)?.strip_prefix('.')?;
+    (!language.is_empty()).then(|| language.to_string())
+}
+
+fn extension(path: &Path) -> String {
+    path.extension()
+        .and_then(|ext| ext.to_str())
+        .unwrap_or_default()
+        .to_ascii_lowercase()
+}
@@ -0,0 +1,195 @@
+use std::collections::BTreeMap;
+
+use crate::deliver::Transfer;
+use crate::failure::ErrorClass;
+use crate::history;
+use crate::units::human_size;
+use crate::urls::UrlEntry;
+use crate::{date, json};
+
+pub enum Status {
+    Downloaded(usize),
+    Skipped,
+    Failed(ErrorClass, String),
+    // Not attempted because the run stopped early; left for the next run
+    Pending,
+}
+
+impl Status {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Status::Downloaded(_) => "downloaded",
+            Status::Skipped => "skipped",
+            Status::Failed(..) => "failed",
+            Status::Pending => "pending",
+        }
+    }
+}
+
+pub struct Outcome {
+    pub url: String,
+    pub source: String,
+    pub status: Status,
+}
+
+#[derive(Default)]
+pub struct Report {
+    pub outcomes: Vec<Outcome>,
+    pub transfers: Vec<Transfer>,
+    // Files finished during the run, as recorded in the history
+    pub files: Vec<history::Entry>,
+    // URLs that only downloaded at a --fallback tier, with its name
+    pub fallbacks: Vec<(String, String)>,
+}
+
+impl Report {
+    pub fn add(&mut self, entry: &UrlEntry, status: Status) {
+        self.outcomes.push(Outcome {
+            url: entry.url.clone(),
+            source: format!("{}:{}", entry.file.display(), entry.line),
+            status,
+        });
+    }
+
+    pub fn failed(&self) -> usize {
+        self.outcomes
+            .iter()
+            .filter(|o| matches!(o.status, Status::Failed(..)))
+            .count()
+    }
+
+    pub fn print_summary(&self) {
+        print!("{}", self.summary());
+    }
+
+    pub fn summary(&self) -> String {
+        let mut lines = Vec::new();
+        let mut files = 0;
+        let mut skipped = 0;
+        let mut pending = 0;
+        let mut failures: BTreeMap<ErrorClass, Vec<&Outcome>> = BTreeMap::new();
+        for outcome in &self.outcomes {
+            match &outcome.status {
+                Status::Downloaded(count) => files += count,
+                Status::Skipped => skipped += 1,
+                Status::Failed(class, _) => failures.entry(*class).or_default().push(outcome),
+                Status::Pending => pending += 1,
+            }
+        }
+
+        let failed: usize = failures.values().map(Vec::len).sum();
+        lines.push(format!(
+            "Run finished: {} URLs processed, {} new files ({}), {} skipped, {} failed.",
+            self.outcomes.len() - pending,
+            files,
+            human_size(self.files.iter().map(|f| f.size).sum()),
+            skipped,
+            failed
+        ));
+        if pending > 0 {
+            lines.push(format!("Left for the next run: {} URLs.", pending));
+        }
+        for (class, outcomes) in &failures {
+            lines.push(match class.hint() {
+                Some(hint) => format!(
+                    "Failed ({}): {} - may help: {}",
+                    class.label(),
+                    outcomes.len(),
+                    hint
+                ),
+                None => format!("Failed ({}): {}", class.label(), outcomes.len()),
+            });
+            for outcome in outcomes {
+                if let Status::Failed(_, message) = &outcome.status {
+                    lines.push(format!(
+                        "  {} ({}): {}",
+                        outcome.url, outcome.source, message
+                    ));
+                }
+            }
+        }
+
+        if !self.fallbacks.is_empty() {
+            lines.push(format!(
+                "Downloaded at a fallback quality: {}",
+                self.fallbacks.len()
+            ));
+            for (url, tier) in &self.fallbacks {
+                lines.push(format!("  {}: {}", url, tier));
+            }
+        }
+
+        if !self.transfers.is_empty() {
+            let (done, failed): (Vec<&Transfer>, Vec<&Transfer>) =
+                self.transfers.iter().partition(|t| t.result.is_ok());
+            let bytes: u64 = done.iter().map(|t| t.size).sum();
+            lines.push(format!(
+                "Transfers: {} files ({}) delivered, {} failed.",
+                done.len(),
+                human_size(bytes),
+                failed.len()
+            ));
+            for transfer in failed {
+                if let Err(message) = &transfer.result {
+                    lines.push(format!(
+                        "  {} -> {}: {}",
+                        transfer.file.display(),
+                        transfer.destination,
+                        message
+                    ));
+                }
+            }
+        }
+
+        lines.push(String::new());
+        lines.join("\n")
+    }
+
+    // The run as a JSON document for --report-json: the counts, failures per error class, and
+    // every URL with its status.
+    pub fn to_json(&self) -> String {
+        let mut failures: BTreeMap<ErrorClass, usize> = BTreeMap::new();
+        let mut urls = Vec::new();
+        for outcome in &self.outcomes {
+            let (files, error, message) = match &outcome.status {
+                Status::Downloaded(files) => (*files, "null".to_string(), "null".to_string()),
+                Status::Failed(class, message) => {
+                    *failures.entry(*class).or_default() += 1;
+                    (0, json::quote(class.label()), json::quote(message))
+                }
+                Status::Skipped | Status::Pending => (0, "null".to_string(), "null".to_string()),
+            };
+            urls.push(format!(
+                "    {{\"url\":{},\"source\":{},\"status\":\"{}\",\"files\":{},\"error\":{},\"message\":{}}}",
+                json::quote(&outcome.url),
+                json::quote(&outcome.source),
+                outcome.status.name(),
+                files,
+                error,
+                message
+            ));
+        }
+        let count = |name: &str| {
+            self.outcomes
+                .iter()
+                .filter(|outcome| outcome.status.name() == name)
+                .count()
+        };
+        let failures: Vec<String> = failures
+            .iter()
+            .map(|(class, count)| format!("\"{}\":{}", class.label(), count))
+            .collect();
+        format!(
+            "{{\n  \"finished\":{},\n  \"downloaded\":{},\n  \"files\":{},\n  \"bytes\":{},\n  \"skipped\":{},\n  \"failed\":{},\n  \"pending\":{},\n  \"failures\":{{{}}},\n  \"urls\":[\n{}\n  ]\n}}\n",
+            json::quote(&date::format_timestamp(date::now())),
+            count("downloaded"),
+            self.files.len(),
+            self.files.iter().map(|f| f.size).sum::<u64>(),
+            count("skipped"),
+            count("failed"),
+            count("pending"),
+            failures.join(","),
+            urls.join(",\n")
+        )
+    }
+}
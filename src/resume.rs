@@ -0,0 +1,77 @@
+use std::collections::HashMap;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use crate::urls::{self, Directives, UrlEntry};
+
+// Remembers the URLs a run stopped before, one `URL FILE LINE` line each, so `--resume` can
+// continue with them. Their directives are read from the .urls file again when resuming.
+pub fn save(path: &Path, entries: &[UrlEntry]) -> io::Result<()> {
+    let contents: String = entries
+        .iter()
+        .map(|entry| format!("{}\t{}\t{}\n", entry.url, entry.file.display(), entry.line))
+        .collect();
+    fs::write(path, contents)
+}
+
+// The URLs saved by the last run that stopped early, None when it did not.
+pub fn load(path: &Path) -> io::Result<Option<Vec<UrlEntry>>> {
+    let contents = match fs::read_to_string(path) {
+        Ok(contents) => contents,
+        Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(None),
+        Err(err) => return Err(err),
+    };
+
+    let mut files: HashMap<PathBuf, Vec<UrlEntry>> = HashMap::new();
+    let mut entries = Vec::new();
+    for line in contents.lines() {
+        let mut fields = line.split('\t');
+        let (Some(url), Some(file), Some(line)) = (
+            fields.next(),
+            fields.next().map(PathBuf::from),
+            fields.next().and_then(|line| line.parse::<usize>().ok()),
+        ) else {
+            continue;
+        };
+        if !files.contains_key(&file) {
+            let listed = if file.is_file() {
+                urls::read_entries(&file)?
+            } else {
+                Vec::new()
+            };
+            files.insert(file.clone(), listed);
+        }
+        let listed = &files[&file];
+
+        // A video of a playlist gets the directives of the playlist's line, like when it was
+        // expanded
+        let (directives, parent) = match listed.iter().find(|entry| entry.line == line) {
+            Some(entry) if entry.url == url => (entry.directives.clone(), None),
+            Some(entry) => (
+                Directives {
+                    items: None,
+                    ..entry.directives.clone()
+                },
+                Some(entry.url.clone()),
+            ),
+            None => (Directives::default(), None),
+        };
+        entries.push(UrlEntry {
+            url: url.to_string(),
+            directives,
+            file,
+            line,
+            parent,
+        });
+    }
+    Ok(Some(entries))
+}
+
+// Forgets the saved URLs once a run got through all of them.
+pub fn clear(path: &Path) -> io::Result<()> {
+    match fs::remove_file(path) {
+        Err(err) if err.kind() != io::ErrorKind::NotFound => Err(err),
+        _ => Ok(()),
+    }
+}
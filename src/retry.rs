@@ -0,0 +1,140 @@
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use crate::failure::ErrorClass;
+use crate::urls::UrlEntry;
+
+// Backoff doubles with every failed attempt, up to this cap.
+const MAX_BACKOFF: u64 = 24 * 3600;
+
+// A URL that failed with an error that may resolve itself, waiting for another attempt.
+#[derive(Debug, Clone)]
+pub struct Pending {
+    pub url: String,
+    pub file: PathBuf,
+    pub class: String,
+    pub attempts: u32,
+    pub first_failed: u64,
+    pub next_attempt: u64,
+    // The playlist URL listed in `file` for one of its videos, see UrlEntry::parent
+    pub parent: Option<String>,
+}
+
+// Failed URLs persisted across runs, so the daemon can re-attempt them on their own schedule.
+pub struct RetryQueue {
+    path: PathBuf,
+    items: Vec<Pending>,
+}
+
+impl RetryQueue {
+    pub fn load(path: &Path) -> io::Result<RetryQueue> {
+        let contents = match fs::read_to_string(path) {
+            Ok(contents) => contents,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => String::new(),
+            Err(err) => return Err(err),
+        };
+
+        let items = contents
+            .lines()
+            .filter_map(|line| {
+                let mut fields = line.split('\t');
+                Some(Pending {
+                    url: fields.next()?.to_string(),
+                    file: PathBuf::from(fields.next()?),
+                    class: fields.next()?.to_string(),
+                    attempts: fields.next()?.parse().ok()?,
+                    first_failed: fields.next()?.parse().ok()?,
+                    next_attempt: fields.next()?.parse().ok()?,
+                    // Missing in queues written before videos of playlists were retried
+                    parent: fields
+                        .next()
+                        .filter(|parent| !parent.is_empty())
+                        .map(str::to_string),
+                })
+            })
+            .collect();
+
+        Ok(RetryQueue {
+            path: path.to_path_buf(),
+            items,
+        })
+    }
+
+    pub fn save(&self) -> io::Result<()> {
+        let contents: String = self
+            .items
+            .iter()
+            .map(|p| {
+                format!(
+                    "{}\t{}\t{}\t{}\t{}\t{}\t{}\n",
+                    p.url,
+                    p.file.display(),
+                    p.class,
+                    p.attempts,
+                    p.first_failed,
+                    p.next_attempt,
+                    p.parent.as_deref().unwrap_or_default()
+                )
+            })
+            .collect();
+        fs::write(&self.path, contents)
+    }
+
+    pub fn record_failure(&mut self, entry: &UrlEntry, class: ErrorClass, backoff: u64, now: u64) {
+        let index = match self.position(&entry.url) {
+            Some(index) => index,
+            None => {
+                self.items.push(Pending {
+                    url: entry.url.clone(),
+                    file: entry.file.clone(),
+                    class: String::new(),
+                    attempts: 0,
+                    first_failed: now,
+                    next_attempt: now,
+                    parent: entry.parent.clone(),
+                });
+                self.items.len() - 1
+            }
+        };
+
+        let pending = &mut self.items[index];
+        pending.class = class.label().to_string();
+        pending.attempts += 1;
+        let delay = backoff.saturating_mul(1 << (pending.attempts - 1).min(16));
+        pending.next_attempt = now + delay.min(MAX_BACKOFF);
+    }
+
+    pub fn clear(&mut self, url: &str) {
+        self.items.retain(|p| p.url != url);
+    }
+
+    // Drops entries that kept failing for longer than `max_age` and returns the ones due now.
+    pub fn due(&mut self, now: u64, max_age: u64) -> Vec<Pending> {
+        self.items.retain(|p| {
+            let expired = now.saturating_sub(p.first_failed) > max_age;
+            if expired {
+                say!(
+                    "Giving up on retrying {} after {} attempts ({}).",
+                    p.url,
+                    p.attempts,
+                    p.class
+                );
+            }
+            !expired
+        });
+        self.items
+            .iter()
+            .filter(|p| p.next_attempt <= now)
+            .cloned()
+            .collect()
+    }
+
+    pub fn next_attempt(&self) -> Option<u64> {
+        self.items.iter().map(|p| p.next_attempt).min()
+    }
+
+    fn position(&self, url: &str) -> Option<usize> {
+        self.items.iter().position(|p| p.url == url)
+    }
+}
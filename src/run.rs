@@ -0,0 +1,1598 @@
+use std::cmp;
+use std::collections::hash_map::RandomState;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::fs;
+use std::hash::BuildHasher;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::aria2::Tuning;
+use crate::cli::Options;
+use crate::collision::{self, OnCollision};
+use crate::deliver::SftpTarget;
+use crate::download::{self, Download, Downloader, Job};
+use crate::dupes::{self, OnDuplicate};
+use crate::failure::{Action, ErrorClass};
+use crate::layout::{self, Layout};
+use crate::probe::{self, PlaylistEntry, Probe};
+use crate::report::{Report, Status};
+use crate::retry::{Pending, RetryQueue};
+use crate::units::human_size;
+use crate::urls::{self, Directives, UrlEntry};
+use crate::{
+    archive, clean, date, deliver, failure, hash, history, manifest, paths, playlist, postprocess,
+    progress, resume, ytdlp,
+};
+use crate::{
+    ATTEMPTS_FILE, DOWNLOADING_FILE, DROPPED_FILE, HISTORY_FILE, INDEX_FILE, NEEDS_AUTH_FILE,
+    OAUTH_DIR, RESUME_FILE, RETRY_FILE,
+};
+
+// Sequence the URLs of a run are downloaded in, from `--order`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Order {
+    // As listed in the .urls files
+    File,
+    // By upload date; undated URLs, such as playlists, last
+    Newest,
+    Oldest,
+    Shuffle,
+    // By estimated size; URLs of unknown size last
+    SmallestFirst,
+}
+
+impl Order {
+    pub fn from_name(name: &str) -> Option<Order> {
+        match name {
+            "file" => Some(Order::File),
+            "newest" => Some(Order::Newest),
+            "oldest" => Some(Order::Oldest),
+            "shuffle" => Some(Order::Shuffle),
+            "smallest-first" => Some(Order::SmallestFirst),
+            _ => None,
+        }
+    }
+
+    // Whether every URL has to be probed before the first download
+    pub fn needs_probe(self) -> bool {
+        matches!(self, Order::Newest | Order::Oldest | Order::SmallestFirst)
+    }
+}
+
+// Why a run ended before every URL was attempted.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Stop {
+    // --abort-on-error
+    Failure,
+    // --max-downloads
+    MaxDownloads,
+    // --max-runtime
+    OutOfTime,
+    // --data-budget
+    DataBudget,
+}
+
+// State of one pass over the .urls files (or over the retry queue in daemon mode).
+pub struct Runner<'a> {
+    opts: &'a Options,
+    known_ids: HashSet<String>,
+    dropped: HashSet<String>,
+    retry_queue: RetryQueue,
+    report: Report,
+    needs_auth: Vec<UrlEntry>,
+    // Playlists already expanded in this pass, in case one lists itself
+    expanded: HashSet<String>,
+    stop: Option<Stop>,
+    // New files so far, for --max-downloads
+    downloaded: usize,
+    // Their bytes, for --data-budget
+    downloaded_bytes: u64,
+    // When --max-runtime runs out
+    deadline: Option<Instant>,
+    // URLs not attempted because the run stopped early
+    pending: Vec<UrlEntry>,
+    // Contents of earlier downloads with --hash, by size and digest
+    digests: BTreeMap<(u64, String), (PathBuf, String)>,
+    // Speeds per host with --downloader aria2c
+    tuning: Tuning,
+    // Probes made for --estimate, by URL, until the download uses them
+    probed: HashMap<String, Probe>,
+    // `yt-dlp --version`, recorded with every download
+    ytdlp_version: Option<String>,
+    // Short-form jobs waiting to be downloaded side by side, with their shared archive keys
+    batch: Vec<(Job, Option<String>)>,
+    // URLs handed to yt-dlp and not recorded yet, as saved to DOWNLOADING_FILE
+    downloading: Vec<UrlEntry>,
+    // Whether this pass went over the .urls files (or the URLs left by the last one), which
+    // decides what --resume continues with
+    full_pass: bool,
+}
+
+impl<'a> Runner<'a> {
+    pub fn new(opts: &'a Options) -> io::Result<Runner<'a>> {
+        Ok(Runner {
+            opts,
+            known_ids: if opts.no_archive {
+                HashSet::new()
+            } else {
+                let mut known_ids = archive::load(&opts.archive_file())?;
+                match &opts.shared_archive {
+                    Some(shared) if opts.no_archive_write => known_ids.extend(shared.load()?),
+                    Some(shared) => shared.merge(&opts.archive_file(), &mut known_ids)?,
+                    None => {}
+                }
+                known_ids
+            },
+            dropped: failure::load_dropped(&opts.state_file(DROPPED_FILE)),
+            retry_queue: RetryQueue::load(&opts.state_file(RETRY_FILE))?,
+            report: Report::default(),
+            needs_auth: Vec::new(),
+            expanded: HashSet::new(),
+            stop: None,
+            downloaded: 0,
+            downloaded_bytes: 0,
+            deadline: opts
+                .max_runtime
+                .map(|secs| Instant::now() + Duration::from_secs(secs)),
+            pending: Vec::new(),
+            probed: HashMap::new(),
+            digests: if opts.hash {
+                dupes::known_digests(&opts.state_file(HISTORY_FILE))?
+            } else {
+                BTreeMap::new()
+            },
+            tuning: if opts.downloader == Downloader::Aria2c {
+                Tuning::new(&history::load(&opts.state_file(HISTORY_FILE))?)
+            } else {
+                Tuning::default()
+            },
+            ytdlp_version: ytdlp::version(),
+            batch: Vec::new(),
+            downloading: Vec::new(),
+            full_pass: false,
+        })
+    }
+
+    // Downloads every URL in the urls directory, or with --resume the URLs the last run
+    // stopped before, if it did. Returns false when there were none.
+    pub fn sync(&mut self) -> io::Result<bool> {
+        self.full_pass = true;
+        let resume_file = self.opts.state_file(RESUME_FILE);
+        let resumed = if self.opts.resume {
+            resume::load(&resume_file)?
+        } else {
+            None
+        };
+        let entries = match resumed {
+            Some(entries) => {
+                say!(
+                    "Resuming with the {} URL(s) the last run did not get to.",
+                    entries.len()
+                );
+                entries
+            }
+            None => {
+                let mut entries = Vec::new();
+                for entry in fs::read_dir(&self.opts.urls_dir)? {
+                    let path = entry?.path();
+                    if path.is_file() {
+                        entries.extend(urls::read_entries(&path)?);
+                    }
+                }
+                entries
+            }
+        };
+        let urls_exist = !entries.is_empty();
+        if urls_exist && (self.opts.estimate || self.opts.dry_run) {
+            self.estimate(&entries)?;
+        }
+        if self.opts.dry_run {
+            return Ok(urls_exist);
+        }
+        // Interrupted downloads go first, while their partial files are fresh
+        let mut interrupted = self.interrupted()?;
+        let first: HashSet<String> = interrupted.iter().map(|entry| entry.url.clone()).collect();
+        interrupted.extend(
+            entries
+                .into_iter()
+                .filter(|entry| !first.contains(&entry.url)),
+        );
+        self.run_all(interrupted)?;
+        Ok(urls_exist)
+    }
+
+    // The URLs whose downloads the last run did not get to finish, by a crash or Ctrl-C, and
+    // whose partial files are still there for yt-dlp to continue from. With --discard-partial
+    // those files are deleted instead.
+    fn interrupted(&mut self) -> io::Result<Vec<UrlEntry>> {
+        let downloading_file = self.opts.state_file(DOWNLOADING_FILE);
+        let Some(entries) = resume::load(&downloading_file)? else {
+            return Ok(Vec::new());
+        };
+        resume::clear(&downloading_file)?;
+
+        let mut interrupted = Vec::new();
+        for entry in entries {
+            let names = download::names_file(self.opts, &entry.url);
+            let files = clean::partial_files_of(&names)?;
+            if files.is_empty() {
+                let _ = fs::remove_file(&names);
+                continue;
+            }
+            if self.opts.discard_partial {
+                for file in &files {
+                    let _ = fs::remove_file(file);
+                }
+                let _ = fs::remove_file(&names);
+                say!(
+                    "Deleted {} partial file(s) of the interrupted download of {}.",
+                    files.len(),
+                    entry.url
+                );
+            } else {
+                say!(
+                    "Continuing the interrupted download of {} from {} partial file(s).",
+                    entry.url,
+                    files.len()
+                );
+                interrupted.push(entry);
+            }
+        }
+        Ok(interrupted)
+    }
+
+    // Keeps DOWNLOADING_FILE up to date as a URL is handed to yt-dlp (`on`) and recorded.
+    fn mark_downloading(&mut self, entry: &UrlEntry, on: bool) -> io::Result<()> {
+        if on {
+            self.downloading.push(entry.clone());
+        } else {
+            self.downloading
+                .retain(|downloading| downloading.url != entry.url);
+        }
+        // Noted afresh by the next download of the URL
+        if !on {
+            let _ = fs::remove_file(download::names_file(self.opts, &entry.url));
+        }
+        let downloading_file = self.opts.state_file(DOWNLOADING_FILE);
+        if self.downloading.is_empty() {
+            resume::clear(&downloading_file)
+        } else {
+            resume::save(&downloading_file, &self.downloading)
+        }
+    }
+
+    // Downloads a list of URLs in order while probing ahead of the downloads.
+    fn run_all(&mut self, mut entries: Vec<UrlEntry>) -> io::Result<()> {
+        if self.opts.order == Order::Shuffle {
+            shuffle(&mut entries);
+        }
+        if self.opts.skip_probe {
+            for entry in entries {
+                if self.stopped() {
+                    self.outcome(&entry, Status::Pending);
+                } else {
+                    self.run_guarded(entry, None)?;
+                }
+            }
+            return self.run_batch();
+        }
+
+        let to_probe = self.to_probe(&entries);
+        if self.opts.order.needs_probe() {
+            let mut probes = Vec::new();
+            probe::probe_in_order(
+                self.opts,
+                &to_probe,
+                self.opts.probe_jobs,
+                |index, probe| -> io::Result<bool> {
+                    probes.push(probe.or_else(|| self.probed.remove(&entries[index].url).map(Ok)));
+                    Ok(true)
+                },
+            )?;
+            for index in self.sorted(&probes) {
+                if self.stopped() {
+                    self.outcome(&entries[index], Status::Pending);
+                } else {
+                    self.run_guarded(entries[index].clone(), probes[index].take())?;
+                }
+            }
+            return self.run_batch();
+        }
+
+        let mut attempted = 0;
+        probe::probe_in_order(
+            self.opts,
+            &to_probe,
+            self.opts.probe_jobs,
+            |index, probe| -> io::Result<bool> {
+                attempted = index + 1;
+                let probe = probe.or_else(|| self.probed.remove(&entries[index].url).map(Ok));
+                self.run_guarded(entries[index].clone(), probe)?;
+                Ok(!self.stopped())
+            },
+        )?;
+        for entry in &entries[attempted..] {
+            self.outcome(entry, Status::Pending);
+        }
+        self.run_batch()
+    }
+
+    // The entries worth probing: URLs that will be skipped anyway, and those probed for the
+    // estimate already, are left out (None).
+    fn to_probe<'e>(&self, entries: &'e [UrlEntry]) -> Vec<Option<&'e UrlEntry>> {
+        let mut seen = HashSet::new();
+        entries
+            .iter()
+            .map(|entry| {
+                let skipped = self.dropped.contains(&entry.url)
+                    || self.probed.contains_key(&entry.url)
+                    || archive::canonical_id(&entry.url)
+                        .is_some_and(|id| self.known_ids.contains(&id) || !seen.insert(id));
+                (!skipped).then_some(entry)
+            })
+            .collect()
+    }
+
+    // Probes the URLs that are going to be downloaded, the videos of playlists included, and
+    // prints how much they add up to per output directory. The downloads reuse the probes.
+    pub fn estimate(&mut self, entries: &[UrlEntry]) -> io::Result<()> {
+        let root = self
+            .opts
+            .final_dir()
+            .unwrap_or_else(|| self.opts.staging_dir());
+        // Per directory: bytes, videos, videos of unknown size
+        let mut dirs: BTreeMap<PathBuf, (u64, usize, usize)> = BTreeMap::new();
+        let mut batch = entries.to_vec();
+        while !batch.is_empty() {
+            let mut children = Vec::new();
+            let to_probe = self.to_probe(&batch);
+            probe::probe_in_order(
+                self.opts,
+                &to_probe,
+                self.opts.probe_jobs,
+                |index, probe| -> io::Result<bool> {
+                    let entry = &batch[index];
+                    if to_probe[index].is_none() {
+                        return Ok(true);
+                    }
+                    let dir = dirs.entry(output_dir_for(&root, &entry.file)).or_default();
+                    match probe {
+                        Some(Ok(probe)) => {
+                            match &probe.entries {
+                                Some(videos) => children.extend(
+                                    videos
+                                        .iter()
+                                        .filter(|video| {
+                                            !video
+                                                .archive_id
+                                                .as_ref()
+                                                .is_some_and(|id| self.known_ids.contains(id))
+                                        })
+                                        .map(|video| child_entry(entry, video.url.clone())),
+                                ),
+                                None => {
+                                    match download::estimated_size(self.opts.quality, &probe) {
+                                        Some(size) => dir.0 += size,
+                                        None => dir.2 += 1,
+                                    }
+                                    dir.1 += 1;
+                                }
+                            }
+                            self.probed.insert(entry.url.clone(), probe);
+                        }
+                        _ => {
+                            dir.1 += 1;
+                            dir.2 += 1;
+                        }
+                    }
+                    Ok(true)
+                },
+            )?;
+            batch = children;
+        }
+
+        let (bytes, videos, unknown) = dirs.values().fold((0, 0, 0), |(b, v, u), dir| {
+            (b + dir.0, v + dir.1, u + dir.2)
+        });
+        println!(
+            "Estimated download: {} in {} videos{}.",
+            human_size(bytes),
+            videos,
+            match unknown {
+                0 => String::new(),
+                unknown => format!(" ({} of them of unknown size, not counted)", unknown),
+            }
+        );
+        for (dir, (bytes, videos, _)) in dirs.iter().filter(|(_, dir)| dir.1 > 0) {
+            println!(
+                "{:>10}  {:>5} videos  {}",
+                human_size(*bytes),
+                videos,
+                dir.display()
+            );
+        }
+        Ok(())
+    }
+
+    // Indices of the probed URLs in `--order`; ties keep their file order.
+    fn sorted(&self, probes: &[Option<Result<Probe, String>>]) -> Vec<usize> {
+        let probe = |index: usize| probes[index].as_ref().and_then(|probe| probe.as_ref().ok());
+        let date = |index| probe(index).and_then(|probe| probe.upload_date.clone());
+        let size = |index| {
+            probe(index).and_then(|probe| download::estimated_size(self.opts.quality, probe))
+        };
+        let mut indices: Vec<usize> = (0..probes.len()).collect();
+        match self.opts.order {
+            Order::Newest => indices.sort_by(|&a, &b| {
+                missing_last(date(a).map(cmp::Reverse), date(b).map(cmp::Reverse))
+            }),
+            Order::Oldest => indices.sort_by(|&a, &b| missing_last(date(a), date(b))),
+            Order::SmallestFirst => indices.sort_by(|&a, &b| missing_last(size(a), size(b))),
+            Order::File | Order::Shuffle => {}
+        }
+        indices
+    }
+
+    // Re-attempts queued failures that are due. URLs no longer listed in their .urls file
+    // are dropped from the queue.
+    pub fn retry_due(&mut self) -> io::Result<usize> {
+        let due = self.retry_queue.due(date::now(), self.opts.retry_max_age);
+
+        for pending in &due {
+            if self.stopped() {
+                break;
+            }
+            match find_entry(pending)? {
+                Some(entry) => {
+                    say!(
+                        "Retrying {} (attempt {}, last failed: {}).",
+                        pending.url,
+                        pending.attempts + 1,
+                        pending.class
+                    );
+                    self.run_entry(entry)?;
+                }
+                None => self.retry_queue.clear(&pending.url),
+            }
+        }
+
+        self.retry_queue.save()?;
+        Ok(due.len())
+    }
+
+    pub fn next_retry(&self) -> Option<u64> {
+        self.retry_queue.next_attempt()
+    }
+
+    pub fn run_entry(&mut self, entry: UrlEntry) -> io::Result<()> {
+        self.run_guarded(entry, None)?;
+        self.run_batch()
+    }
+
+    // Runs one URL so that an error on the way, such as a full disk, fails that URL instead of
+    // ending the run. Only errors that keep the run from recording that failure get through.
+    fn run_guarded(
+        &mut self,
+        entry: UrlEntry,
+        probe: Option<Result<Probe, String>>,
+    ) -> io::Result<()> {
+        let Err(err) = self.run_probed(entry.clone(), probe) else {
+            return Ok(());
+        };
+        say!("Processing {} failed: {}", entry.url, err);
+        self.mark_downloading(&entry, false)?;
+        // Later entries for the same video get their chance in this run
+        if let Some(id) = archive::canonical_id(&entry.url) {
+            self.known_ids.remove(&id);
+        }
+        let message = err.to_string();
+        history::record_attempt(
+            &self.opts.state_file(ATTEMPTS_FILE),
+            &entry.url,
+            Some(ErrorClass::Unknown),
+        )?;
+        self.opts
+            .notify
+            .failed(&entry, ErrorClass::Unknown, &message);
+        self.outcome(&entry, Status::Failed(ErrorClass::Unknown, message));
+        Ok(())
+    }
+
+    // Downloads one URL with the result of probing it, or probes it here first when that did
+    // not happen yet (None) and --skip-probe is not set.
+    fn run_probed(
+        &mut self,
+        entry: UrlEntry,
+        probe: Option<Result<Probe, String>>,
+    ) -> io::Result<()> {
+        if self.dropped.contains(&entry.url) {
+            say!(
+                "Skipping {} ({}:{}): dropped after an earlier failure, see {}.",
+                entry.url,
+                entry.file.display(),
+                entry.line,
+                self.opts.state_file(DROPPED_FILE).display()
+            );
+            self.outcome(&entry, Status::Skipped);
+            return Ok(());
+        }
+
+        // Skip videos we already have before spending a yt-dlp process on them
+        let canonical_id = archive::canonical_id(&entry.url);
+        if let Some(id) = &canonical_id {
+            if !self.known_ids.insert(id.clone()) {
+                say!(
+                    "Skipping {} ({}:{}): {} is already downloaded or queued.",
+                    entry.url,
+                    entry.file.display(),
+                    entry.line,
+                    id
+                );
+                self.retry_queue.clear(&entry.url);
+                self.outcome(&entry, Status::Skipped);
+                return Ok(());
+            }
+        }
+
+        let probe = match probe {
+            Some(probe) => Some(probe),
+            None if !self.opts.skip_probe => Some(probe::probe(self.opts, &entry)),
+            None => None,
+        };
+        // Set when the URL fails before it is handed to yt-dlp
+        let mut failed = None;
+        let probe = match probe {
+            Some(Ok(probe)) => Some(probe),
+            Some(Err(err)) => {
+                let class = failure::classify(std::slice::from_ref(&err));
+                if class.unavailable() && !(class.needs_auth() && self.opts.has_credentials()) {
+                    // The download would not get any further
+                    failed = Some(Status::Failed(class, err));
+                } else {
+                    say!(
+                        "Probing {} failed, downloading without it: {}",
+                        entry.url,
+                        err
+                    );
+                }
+                None
+            }
+            None => None,
+        };
+        let layout = Layout::for_url(&entry.url, entry.directives.layout.or(self.opts.layout));
+        // Albums are handed to yt-dlp whole, which numbers their tracks
+        if let (
+            Some(Probe {
+                entries: Some(videos),
+                ..
+            }),
+            Layout::Default | Layout::Shorts,
+        ) = (&probe, layout)
+        {
+            return self.expand(entry, videos.clone());
+        }
+
+        // Sizes the site reports, checked before spending any data on the video
+        let size = probe
+            .as_ref()
+            .and_then(|probe| download::estimated_size(self.opts.quality, probe));
+        if let (Some(size), Some(max)) = (size, self.opts.max_filesize) {
+            if size > max {
+                say!(
+                    "Skipping {} ({}:{}): about {}, more than --max-filesize.",
+                    entry.url,
+                    entry.file.display(),
+                    entry.line,
+                    human_size(size)
+                );
+                self.outcome(&entry, Status::Skipped);
+                return Ok(());
+            }
+        }
+        if let (Some(size), Some(left)) = (size, self.budget_left()) {
+            if size > left {
+                if let Some(id) = &canonical_id {
+                    self.known_ids.remove(id);
+                }
+                self.stop = Some(Stop::DataBudget);
+                self.outcome(&entry, Status::Pending);
+                return Ok(());
+            }
+        }
+
+        // The key other machines know the video by, if it is known before downloading
+        let shared_id = canonical_id
+            .clone()
+            .or_else(|| probe.as_ref().and_then(|probe| probe.archive_id.clone()))
+            .filter(|_| self.opts.shared_archive.is_some() && !self.opts.no_archive_write);
+
+        let output_dir = self.output_dir(&entry, layout);
+        fs::create_dir_all(&output_dir)?;
+        let mut output_template = match layout {
+            Layout::Default => download::OUTPUT_TEMPLATE,
+            Layout::Music => layout::MUSIC_TEMPLATE,
+            Layout::Shorts => layout::SHORTS_TEMPLATE,
+        };
+        if let Some(existing) = match (&probe, layout) {
+            (Some(probe), Layout::Default) => self.collision(&output_dir, probe)?,
+            _ => None,
+        } {
+            match self.opts.on_collision {
+                OnCollision::SuffixId => {
+                    say!(
+                        "{} would be saved over {}, adding the video id to its name.",
+                        entry.url,
+                        existing.display()
+                    );
+                    output_template = download::OUTPUT_TEMPLATE_WITH_ID;
+                }
+                OnCollision::Skip => {
+                    say!(
+                        "Skipping {} ({}:{}): it would be saved over {}.",
+                        entry.url,
+                        entry.file.display(),
+                        entry.line,
+                        existing.display()
+                    );
+                    if let Some(id) = &canonical_id {
+                        self.known_ids.remove(id);
+                    }
+                    self.outcome(&entry, Status::Skipped);
+                    return Ok(());
+                }
+                OnCollision::Overwrite => {
+                    say!("{} replaces {}.", entry.url, existing.display())
+                }
+                OnCollision::Error => {
+                    failed = Some(Status::Failed(
+                        ErrorClass::Unknown,
+                        format!("it would be saved over {}", existing.display()),
+                    ))
+                }
+            }
+        }
+        let aria2c = (self.opts.downloader == Downloader::Aria2c).then(|| {
+            self.tuning
+                .settings(&entry.url, self.opts.aria2c_connections)
+        });
+        let job = Job {
+            entry,
+            output_dir,
+            canonical_id,
+            probe,
+            max_downloads: self
+                .opts
+                .max_downloads
+                .map(|max| max.saturating_sub(self.downloaded)),
+            max_filesize: match (self.opts.max_filesize, self.budget_left()) {
+                (Some(max), Some(left)) => Some(max.min(left)),
+                (max, left) => max.or(left),
+            },
+            output_template,
+            layout,
+            // Limits that count every file as it finishes need the downloads one at a time
+            batched: failed.is_none()
+                && layout == Layout::Shorts
+                && self.opts.shorts_jobs > 1
+                && self.opts.max_downloads.is_none()
+                && self.opts.data_budget.is_none(),
+            aria2c,
+            overwrite: self.opts.on_collision == OnCollision::Overwrite,
+        };
+
+        if let (None, Some(shared), Some(id)) = (&failed, &self.opts.shared_archive, &shared_id) {
+            if !shared.claim(id)? {
+                say!(
+                    "Skipping {} ({}:{}): {} is downloaded by another machine.",
+                    job.entry.url,
+                    job.entry.file.display(),
+                    job.entry.line,
+                    id
+                );
+                self.outcome(&job.entry, Status::Skipped);
+                return Ok(());
+            }
+        }
+        if failed.is_none() {
+            self.mark_downloading(&job.entry, true)?;
+        }
+        if job.batched {
+            self.batch.push((job, shared_id));
+            if self.batch.len() >= self.opts.shorts_jobs {
+                self.run_batch()?;
+            }
+            return Ok(());
+        }
+        // From here on an error fails the job like a failed download, so the claim is given back
+        let status = match failed {
+            Some(status) => status,
+            None => match self.process_job(&job, None) {
+                Ok(status) => status,
+                Err(err) => {
+                    say!("Processing {} failed: {}", job.entry.url, err);
+                    Status::Failed(ErrorClass::Unknown, err.to_string())
+                }
+            },
+        };
+        self.complete(job, shared_id, status)
+    }
+
+    // Downloads the short-form jobs waiting in the batch side by side, then records them in
+    // order. The ones that failed get their retries from `process_job` one at a time.
+    fn run_batch(&mut self) -> io::Result<()> {
+        let batch = std::mem::take(&mut self.batch);
+        if batch.is_empty() {
+            return Ok(());
+        }
+        say!("Downloading {} short-form videos at once.", batch.len());
+        let opts = self.opts;
+        let archive_file = opts.archive_file();
+        let oauth_dir = opts.state_file(OAUTH_DIR);
+        let results: Vec<io::Result<(Download, Duration)>> = thread::scope(|scope| {
+            let downloads: Vec<_> = batch
+                .iter()
+                .map(|(job, _)| {
+                    scope.spawn(|| {
+                        let started = Instant::now();
+                        let result =
+                            download::download(opts, job, &archive_file, &oauth_dir, false, None)?;
+                        Ok((result, started.elapsed()))
+                    })
+                })
+                .collect();
+            downloads
+                .into_iter()
+                .map(|download| {
+                    download
+                        .join()
+                        .unwrap_or_else(|_| Err(io::Error::other("the download thread panicked")))
+                })
+                .collect()
+        });
+
+        for ((job, shared_id), result) in batch.into_iter().zip(results) {
+            let status = match result.and_then(|first| self.process_job(&job, Some(first))) {
+                Ok(status) => status,
+                Err(err) => {
+                    say!("Processing {} failed: {}", job.entry.url, err);
+                    Status::Failed(ErrorClass::Unknown, err.to_string())
+                }
+            };
+            self.complete(job, shared_id, status)?;
+        }
+        Ok(())
+    }
+
+    // Records how a job ended: in the shared archive, the attempts and the failure policies.
+    fn complete(&mut self, job: Job, shared_id: Option<String>, status: Status) -> io::Result<()> {
+        if let (Status::Failed(..), true) = (&status, self.opts.discard_partial) {
+            let names = download::names_file(self.opts, &job.entry.url);
+            for file in clean::partial_files_of(&names)? {
+                let _ = fs::remove_file(file);
+            }
+        }
+        self.mark_downloading(&job.entry, false)?;
+        if let (Status::Failed(..), Some(shared), Some(id)) =
+            (&status, &self.opts.shared_archive, &shared_id)
+        {
+            shared.release(id)?;
+        }
+        if let (Status::Failed(..), Some(id)) = (&status, &job.canonical_id) {
+            self.known_ids.remove(id);
+        }
+        let failure = match &status {
+            Status::Failed(class, _) => Some(*class),
+            _ => None,
+        };
+        history::record_attempt(
+            &self.opts.state_file(ATTEMPTS_FILE),
+            &job.entry.url,
+            failure,
+        )?;
+        match &status {
+            Status::Failed(class, message) => {
+                self.opts.notify.failed(&job.entry, *class, message);
+                if class.needs_auth() {
+                    self.needs_auth.push(job.entry.clone());
+                }
+                self.apply_policy(&job.entry, *class, message)?;
+            }
+            _ => self.retry_queue.clear(&job.entry.url),
+        }
+        self.outcome(&job.entry, status);
+        Ok(())
+    }
+
+    // Downloads the videos of a playlist or channel one by one, each with its own archive check
+    // and format selection, instead of handing the whole list to one yt-dlp process.
+    fn expand(&mut self, entry: UrlEntry, videos: Vec<PlaylistEntry>) -> io::Result<()> {
+        if !self.expanded.insert(entry.url.clone()) {
+            say!("Skipping {}: it lists itself.", entry.url);
+            self.outcome(&entry, Status::Skipped);
+            return Ok(());
+        }
+
+        let total = videos.len();
+        let mut children = Vec::new();
+        for video in videos {
+            let child = child_entry(&entry, video.url);
+            match video.archive_id {
+                Some(id) if self.known_ids.contains(&id) => self.outcome(&child, Status::Skipped),
+                _ => children.push(child),
+            }
+        }
+        say!(
+            "{} ({}:{}) lists {} videos, {} of them not downloaded yet.",
+            entry.url,
+            entry.file.display(),
+            entry.line,
+            total,
+            children.len()
+        );
+        self.run_all(children)
+    }
+
+    // Records how a URL ended, and announces it with --progress-json.
+    fn outcome(&mut self, entry: &UrlEntry, status: Status) {
+        if self.opts.progress_json {
+            let id = archive::canonical_id(&entry.url);
+            progress::Event {
+                phase: "done",
+                url: &entry.url,
+                id: id.as_deref(),
+                status: Some(status.name()),
+                message: match &status {
+                    Status::Failed(_, message) => Some(message),
+                    _ => None,
+                },
+                ..Default::default()
+            }
+            .emit();
+        }
+        if self.opts.porcelain {
+            println!("{}", porcelain_line(entry, &status));
+        }
+        if self.opts.abort_on_error && matches!(status, Status::Failed(..)) {
+            self.stop = Some(Stop::Failure);
+        }
+        if let Status::Pending = status {
+            self.pending.push(entry.clone());
+        }
+        self.report.add(entry, status);
+    }
+
+    // Whether to stop before the next URL; checks --max-runtime and --data-budget.
+    fn stopped(&mut self) -> bool {
+        if self.stop.is_none() && self.deadline.is_some_and(|at| Instant::now() >= at) {
+            self.stop = Some(Stop::OutOfTime);
+        }
+        if self.stop.is_none() && self.budget_left() == Some(0) {
+            self.stop = Some(Stop::DataBudget);
+        }
+        self.stop.is_some()
+    }
+
+    fn budget_left(&self) -> Option<u64> {
+        self.opts
+            .data_budget
+            .map(|budget| budget.saturating_sub(self.downloaded_bytes))
+    }
+
+    // Announces a step on a finished file with --progress-json.
+    fn phase(&self, phase: &str, record: &history::Entry) {
+        if self.opts.progress_json {
+            progress::Event {
+                phase,
+                url: &record.url,
+                id: Some(&record.archive_id),
+                ..Default::default()
+            }
+            .emit();
+        }
+    }
+
+    // Where yt-dlp saves the files of a URL in the staging directory.
+    fn output_dir(&self, entry: &UrlEntry, layout: Layout) -> PathBuf {
+        match layout {
+            Layout::Shorts => output_dir_for(
+                &self.opts.staging_dir().join(layout::SHORTS_DIR),
+                &entry.file,
+            ),
+            _ => output_dir_for(&self.opts.staging_dir(), &entry.file),
+        }
+    }
+
+    // An existing file a video would be saved over, in the staging directory or where it ends
+    // up. The file recorded for the same video, downloaded again, does not count.
+    fn collision(&self, output_dir: &Path, probe: &Probe) -> io::Result<Option<PathBuf>> {
+        let Some(title) = &probe.title else {
+            return Ok(None);
+        };
+        let mut dirs = vec![output_dir.to_path_buf()];
+        dirs.extend(
+            self.opts
+                .final_dir()
+                .map(|dir| dir.join(self.relative_path(output_dir))),
+        );
+        let Some(existing) = collision::existing(&dirs, &collision::file_stem(title)) else {
+            return Ok(None);
+        };
+        let same_video = history::load(&self.opts.state_file(HISTORY_FILE))?
+            .iter()
+            .any(|entry| {
+                entry.path == existing && probe.archive_id.as_ref() == Some(&entry.archive_id)
+            });
+        Ok((!same_video).then_some(existing))
+    }
+
+    // Downloads a job, retrying videos that need an account with the configured cookies or
+    // OAuth login, and records every finished file. `first` is the first attempt when it was
+    // made in a batch already, with how long it took.
+    fn process_job(
+        &mut self,
+        job: &Job,
+        mut first: Option<(Download, Duration)>,
+    ) -> io::Result<Status> {
+        let opts = self.opts;
+        let archive_file = opts.archive_file();
+        let oauth_dir = opts.state_file(OAUTH_DIR);
+        let mut authenticated = false;
+        let mut retries = 0;
+        // Index into --fallback once the format failed
+        let mut tier: Option<usize> = None;
+        let mut finished = Vec::new();
+        let mut elapsed = Duration::ZERO;
+        let (status, limited, errors, class) = loop {
+            let (result, took) = match first.take() {
+                Some(first) => first,
+                None => {
+                    let started = Instant::now();
+                    let result = download::download(
+                        opts,
+                        job,
+                        &archive_file,
+                        &oauth_dir,
+                        authenticated,
+                        tier.map(|tier| &opts.fallback[tier]),
+                    )?;
+                    (result, started.elapsed())
+                }
+            };
+            // Without progress from yt-dlp, the whole run counts as transfer time
+            elapsed += if result.transfer_seconds > 0.0 {
+                Duration::from_secs_f64(result.transfer_seconds)
+            } else {
+                took
+            };
+            say!("Download finished with exit status: {}", result.status);
+            finished.extend(result.finished);
+
+            let class = match failure::classify(&result.errors) {
+                // A connection that broke off after crawling along was most likely throttled
+                ErrorClass::Network | ErrorClass::Unknown if result.throttled => {
+                    ErrorClass::Throttled
+                }
+                class => class,
+            };
+            if result.status.success() || result.limited {
+                break (result.status, result.limited, result.errors, class);
+            }
+            if class.needs_auth() && !authenticated && opts.has_credentials() {
+                say!(
+                    "{} is {}, retrying with your account.",
+                    job.entry.url,
+                    class.label()
+                );
+                authenticated = true;
+                continue;
+            }
+
+            if class == ErrorClass::Format {
+                let next = tier.map_or(0, |tier| tier + 1);
+                if let Some(fallback) = opts.fallback.get(next) {
+                    say!(
+                        "{} failed ({}), retrying at {}.",
+                        job.entry.url,
+                        class.label(),
+                        fallback.name
+                    );
+                    tier = Some(next);
+                    continue;
+                }
+            }
+
+            let policy = opts.policies.get(class);
+            if retries < policy.retries {
+                retries += 1;
+                let delay = policy.delay * u64::from(retries);
+                say!(
+                    "{} failed ({}), retrying in {}s ({}/{}).",
+                    job.entry.url,
+                    class.label(),
+                    delay,
+                    retries,
+                    policy.retries
+                );
+                thread::sleep(Duration::from_secs(delay));
+                continue;
+            }
+            break (result.status, result.limited, result.errors, class);
+        };
+
+        // Split the time yt-dlp ran over the files it finished, by size
+        let total: u64 = finished.iter().map(|record| record.size).sum();
+        for record in &mut finished {
+            record.ytdlp_version = self.ytdlp_version.clone();
+            if total > 0 {
+                record.seconds = elapsed.as_secs_f64() * record.size as f64 / total as f64;
+            }
+        }
+
+        if let (Some(shared), false) = (&opts.shared_archive, opts.no_archive_write) {
+            let ids: Vec<String> = finished
+                .iter()
+                .map(|record| record.archive_id.clone())
+                .collect();
+            shared.add(&ids)?;
+        }
+
+        let mut files = 0;
+        // Steps that change the file, after which it is hashed again for the history
+        let converts =
+            opts.normalize_audio || (opts.burn_subs.is_some() && !opts.burn_subs_separate);
+        for mut record in finished {
+            // Hashed as yt-dlp left it, so a duplicate is deleted before it is post-processed and
+            // is recognized however it would have been post-processed
+            let downloaded = if opts.hash {
+                record.hash = digest(&record.path);
+                if self.is_duplicate(&record) && opts.on_duplicate == OnDuplicate::Skip {
+                    for file in deliver::sidecars(&record.path)
+                        .into_iter()
+                        .chain([record.path.clone()])
+                    {
+                        let _ = fs::remove_file(file);
+                    }
+                    continue;
+                }
+                record.hash.clone().map(|hash| (record.size, hash))
+            } else {
+                None
+            };
+            self.phase("postprocess", &record);
+            let (postprocessed, mut original) = self.postprocess(&mut record);
+            if opts.hash && converts {
+                record.hash = digest(&record.path);
+                // The original is the download, untouched
+                if let Some(original) = &mut original {
+                    original.hash = downloaded.as_ref().map(|(_, hash)| hash.clone());
+                }
+            }
+            files += 1;
+            self.phase("deliver", &record);
+            if let Some(mut original) = original {
+                self.deliver(&mut original, postprocessed);
+                history::append(&self.opts.state_file(HISTORY_FILE), &original)?;
+            }
+            self.deliver(&mut record, postprocessed);
+            if opts
+                .final_dir()
+                .is_some_and(|dir| record.path.starts_with(dir))
+            {
+                if let Err(err) = manifest::add(&record) {
+                    say!(
+                        "Updating the {} next to {} failed: {}",
+                        INDEX_FILE,
+                        record.path.display(),
+                        err
+                    );
+                }
+            }
+
+            self.known_ids.insert(record.archive_id.clone());
+            history::append(&self.opts.state_file(HISTORY_FILE), &record)?;
+            self.tuning.record(&record);
+            self.opts.notify.downloaded(&record);
+            self.downloaded_bytes += record.size;
+            if let Some(key) = downloaded {
+                self.digests
+                    .insert(key, (record.path.clone(), record.archive_id.clone()));
+            }
+            if let Some(hash) = &record.hash {
+                self.digests.insert(
+                    (record.size, hash.clone()),
+                    (record.path.clone(), record.archive_id.clone()),
+                );
+            }
+            self.report.files.push(record);
+        }
+
+        self.downloaded += files;
+        if opts.max_downloads.is_some_and(|max| self.downloaded >= max) {
+            self.stop = Some(Stop::MaxDownloads);
+        }
+
+        if status.success() || limited {
+            if let Some(tier) = tier {
+                self.report
+                    .fallbacks
+                    .push((job.entry.url.clone(), opts.fallback[tier].name.clone()));
+            }
+            Ok(Status::Downloaded(files))
+        } else {
+            let message = errors
+                .last()
+                .map(|line| line.trim_start_matches("ERROR:").trim().to_string())
+                .unwrap_or_else(|| format!("yt-dlp exited with {}", status));
+            Ok(Status::Failed(class, message))
+        }
+    }
+
+    // Whether a new file has the same contents as one downloaded before, which is mentioned.
+    fn is_duplicate(&self, record: &history::Entry) -> bool {
+        let Some(hash) = &record.hash else {
+            return false;
+        };
+        // The same video downloaded again is no duplicate
+        let Some((existing, _)) = self
+            .digests
+            .get(&(record.size, hash.clone()))
+            .filter(|(_, archive_id)| *archive_id != record.archive_id)
+        else {
+            return false;
+        };
+        say!(
+            "{} has the same contents as {}{}.",
+            record.path.display(),
+            existing.display(),
+            match self.opts.on_duplicate {
+                OnDuplicate::Warn => "",
+                OnDuplicate::Skip => ", deleting it",
+            }
+        );
+        true
+    }
+
+    // Runs the enabled post-processing steps on a finished file. Returns false if one failed,
+    // and with --keep-original the record of the untouched download once a step changed it.
+    fn postprocess(&self, record: &mut history::Entry) -> (bool, Option<history::Entry>) {
+        let opts = self.opts;
+        let mut ok = true;
+        let mut converted = false;
+        let mut original = None;
+        let burn_in_place = opts.burn_subs.is_some() && !opts.burn_subs_separate;
+        if opts.keep_original && (opts.normalize_audio || burn_in_place) {
+            match postprocess::keep_original(&record.path) {
+                Ok(path) => original = Some(path),
+                Err(err) => say!(
+                    "Keeping the original of {} failed: {}",
+                    record.path.display(),
+                    err
+                ),
+            }
+        }
+        if opts.normalize_audio {
+            say!("Normalizing audio: {}", record.path.display());
+            match postprocess::normalize_audio(&record.path) {
+                Ok(()) => converted = true,
+                Err(err) => {
+                    say!("Audio normalization failed: {}", err);
+                    ok = false;
+                }
+            }
+        }
+        if let Some(language) = &opts.burn_subs {
+            // A `<title>.<lang>-burned.<ext>` sidecar, or the file itself
+            let output = if opts.burn_subs_separate {
+                postprocess::sibling_with_suffix(&record.path, &format!("{}-burned", language))
+            } else {
+                record.path.clone()
+            };
+            say!("Burning {} subtitles into {}", language, output.display());
+            match postprocess::burn_subtitles(&record.path, language, &output) {
+                Ok(()) => converted |= burn_in_place,
+                Err(err) => {
+                    say!("Burning in subtitles failed: {}", err);
+                    ok = false;
+                }
+            }
+        }
+        record.size = fs::metadata(&record.path).map_or(record.size, |m| m.len());
+
+        // Nothing was converted, so there is nothing to keep
+        if !converted {
+            if let Some(path) = original.take() {
+                let _ = fs::remove_file(path);
+            }
+        }
+        let original = original.map(|path| history::Entry {
+            size: fs::metadata(&path).map_or(0, |m| m.len()),
+            path,
+            // The download time is counted once, for the converted file
+            seconds: 0.0,
+            hash: None,
+            connections: None,
+            ..record.clone()
+        });
+        (ok, original)
+    }
+
+    // Moves a post-processed file into place and sends it to the upload and SFTP destinations.
+    fn deliver(&mut self, record: &mut history::Entry, postprocessed: bool) {
+        let opts = self.opts;
+        if let Some(destination) = opts.final_dir() {
+            if postprocessed {
+                self.finalize(record, &destination);
+            } else {
+                say!(
+                    "Leaving {} in {} because post-processing failed.",
+                    record.path.display(),
+                    opts.staging_dir().display()
+                );
+            }
+        }
+        if let Some(remote) = &opts.upload {
+            self.upload(record, remote);
+        }
+        if let Some(target) = opts.sftp_target() {
+            self.send_sftp(record, &target);
+        }
+    }
+
+    // Renames a finished file and its sidecars from the staging directory into place below
+    // `destination`, keeping the collection subdirectories.
+    fn finalize(&mut self, record: &mut history::Entry, destination: &Path) {
+        let mut files = vec![record.path.clone()];
+        files.extend(self.sidecars(&record.path));
+
+        for file in files {
+            let relative = self.relative_path(&file);
+            let transfer = deliver::move_file(&file, &destination.join(&relative));
+            match &transfer.result {
+                Ok(()) if file == record.path => {
+                    say!("Saved {}", transfer.destination);
+                    record.path = PathBuf::from(&transfer.destination);
+                }
+                Ok(()) => {}
+                Err(err) => say!("Moving {} into place failed: {}", file.display(), err),
+            }
+            if self.opts.move_to.is_some() || transfer.result.is_err() {
+                self.report.transfers.push(transfer);
+            }
+        }
+    }
+
+    // Uploads a finished file and its sidecars. After a verified `--move` the history records
+    // the remote location instead of the deleted local file.
+    fn upload(&mut self, record: &mut history::Entry, remote: &str) {
+        let mut files = vec![record.path.clone()];
+        files.extend(self.sidecars(&record.path));
+
+        for file in files {
+            let relative = self.relative_path(&file);
+            say!("Uploading {} to {}", file.display(), remote);
+            let transfer = deliver::upload(&file, remote, &relative, self.opts.upload_move);
+            match &transfer.result {
+                Ok(()) if file == record.path && self.opts.upload_move => {
+                    record.path = PathBuf::from(&transfer.destination);
+                }
+                Ok(()) => {}
+                Err(err) => say!("Upload of {} failed: {}", file.display(), err),
+            }
+            self.report.transfers.push(transfer);
+        }
+    }
+
+    // Sends a finished file and its sidecars to the SFTP destination, recording the remote
+    // location once the local copy is gone.
+    fn send_sftp(&mut self, record: &mut history::Entry, target: &SftpTarget) {
+        let mut files = vec![record.path.clone()];
+        files.extend(self.sidecars(&record.path));
+
+        for file in files {
+            let relative = self.relative_path(&file);
+            say!("Sending {} to {}", file.display(), target.host);
+            let transfer = deliver::sftp_upload(&file, target, &relative);
+            match &transfer.result {
+                Ok(()) if file == record.path => {
+                    record.path = PathBuf::from(&transfer.destination);
+                }
+                Ok(()) => {}
+                Err(err) => say!("Sending {} failed: {}", file.display(), err),
+            }
+            self.report.transfers.push(transfer);
+        }
+    }
+
+    // Files that go along with a download: its sidecars, and while it is still being staged, the
+    // album art of the music layout next to it.
+    fn sidecars(&self, file: &Path) -> Vec<PathBuf> {
+        let mut files = deliver::sidecars(file);
+        if file.starts_with(self.opts.staging_dir()) {
+            files.extend(
+                file.parent()
+                    .map(|dir| dir.join("cover.jpg"))
+                    .filter(|cover| cover.is_file()),
+            );
+        }
+        files
+    }
+
+    // Path of a downloaded file below the output directory, as laid out at a destination.
+    fn relative_path(&self, file: &Path) -> PathBuf {
+        let roots = [Some(self.opts.staging_dir()), self.opts.final_dir()];
+        roots
+            .iter()
+            .flatten()
+            .find_map(|root| file.strip_prefix(root).ok())
+            .unwrap_or(file)
+            .to_path_buf()
+    }
+
+    // Applies the configured failure policy once a URL's immediate retries are used up.
+    fn apply_policy(
+        &mut self,
+        entry: &UrlEntry,
+        class: ErrorClass,
+        message: &str,
+    ) -> io::Result<()> {
+        // A video reported removed run after run is not coming back
+        if let (ErrorClass::Removed, Some(runs)) = (class, self.opts.mark_dead_after) {
+            let streak = history::load_attempts(&self.opts.state_file(ATTEMPTS_FILE))?
+                .iter()
+                .rev()
+                .filter(|attempt| attempt.url == entry.url)
+                .take_while(|attempt| attempt.failure == Some(ErrorClass::Removed))
+                .count();
+            if streak >= runs {
+                self.retry_queue.clear(&entry.url);
+                let reason = format!("dead link, removed in {} runs: {}", streak, message);
+                if urls::comment_out(entry, &reason)? {
+                    say!(
+                        "Marked {} in {}:{} as dead after it was reported removed in {} runs.",
+                        entry.url,
+                        entry.file.display(),
+                        entry.line,
+                        streak
+                    );
+                } else {
+                    self.not_commented_out(entry);
+                }
+                return Ok(());
+            }
+        }
+
+        let action = match self.opts.policies.get(class).action {
+            // Upcoming videos become available by themselves
+            _ if self.opts.comment_out_unavailable
+                && class.unavailable()
+                && class != ErrorClass::Upcoming =>
+            {
+                Action::Annotate
+            }
+            action => action,
+        };
+        match action {
+            Action::Keep => self.retry_queue.clear(&entry.url),
+            Action::Requeue => {
+                self.retry_queue
+                    .record_failure(entry, class, self.opts.retry_backoff, date::now());
+            }
+            Action::Drop => {
+                self.retry_queue.clear(&entry.url);
+                if self.dropped.insert(entry.url.clone()) {
+                    failure::record_dropped(
+                        &self.opts.state_file(DROPPED_FILE),
+                        entry,
+                        class,
+                        message,
+                    )?;
+                    say!(
+                        "Dropped {} ({}), see {}.",
+                        entry.url,
+                        class.label(),
+                        self.opts.state_file(DROPPED_FILE).display()
+                    );
+                }
+            }
+            Action::Annotate => {
+                self.retry_queue.clear(&entry.url);
+                if urls::comment_out(entry, &format!("{}: {}", class.label(), message))? {
+                    say!(
+                        "Commented out {} in {}:{} ({}).",
+                        entry.url,
+                        entry.file.display(),
+                        entry.line,
+                        class.label()
+                    );
+                } else {
+                    self.not_commented_out(entry);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    // The line of a playlist stays as it is for one of its videos; says so rather than nothing.
+    fn not_commented_out(&self, entry: &UrlEntry) {
+        if let Some(parent) = &entry.parent {
+            say!(
+                "Not commenting out {}: it is a video of {} in {}:{}, which stays listed.",
+                entry.url,
+                parent,
+                entry.file.display(),
+                entry.line
+            );
+        }
+    }
+
+    // Prints the summary and persists what the next run needs.
+    // Returns the exit status: 0, 1 when a URL failed, or 3 when --max-runtime ran out.
+    pub fn finish(self) -> io::Result<i32> {
+        if !self.opts.porcelain {
+            self.report.print_summary();
+        }
+        if let Some(file) = &self.opts.report_json {
+            fs::write(file, self.report.to_json())?;
+        }
+        match self.stop {
+            Some(Stop::Failure) => say!("Stopped at the first failure (--abort-on-error); the remaining URLs were not attempted."),
+            Some(Stop::MaxDownloads) => say!(
+                "Stopped after {} new files (--max-downloads); the remaining URLs are left for the next run.",
+                self.downloaded
+            ),
+            Some(Stop::DataBudget) => say!(
+                "Stopped after {} (--data-budget); the remaining URLs are left for the next run.",
+                human_size(self.downloaded_bytes)
+            ),
+            Some(Stop::OutOfTime) => say!("Stopped after --max-runtime; the remaining URLs were not attempted."),
+            None => {}
+        }
+        if self.full_pass {
+            let resume_file = self.opts.state_file(RESUME_FILE);
+            if self.pending.is_empty() {
+                resume::clear(&resume_file)?;
+            } else {
+                resume::save(&resume_file, &self.pending)?;
+                say!(
+                    "Saved the {} remaining URL(s) to {}; `dlyt run --resume` continues with them.",
+                    self.pending.len(),
+                    resume_file.display()
+                );
+            }
+        }
+        self.opts.notify.run_finished(&self.report);
+
+        // Playlists of the collections that got new videos
+        if let Some(final_dir) = self.opts.final_dir() {
+            let mut dirs: Vec<&Path> = self
+                .report
+                .files
+                .iter()
+                .filter(|record| record.path.starts_with(&final_dir))
+                .filter_map(|record| record.path.parent())
+                .collect();
+            dirs.sort();
+            dirs.dedup();
+            for dir in dirs {
+                if let Err(err) = playlist::write(dir, self.opts.playlist_order) {
+                    say!("Writing the playlist of {} failed: {}", dir.display(), err);
+                }
+            }
+        }
+        self.retry_queue.save()?;
+
+        if !self.needs_auth.is_empty() {
+            failure::record_needs_auth(Path::new(NEEDS_AUTH_FILE), &self.needs_auth)?;
+            say!(
+                "Added {} URL(s) that need an account to {} for a later authenticated pass.",
+                self.needs_auth.len(),
+                NEEDS_AUTH_FILE
+            );
+        }
+        Ok(if self.stop == Some(Stop::OutOfTime) {
+            3
+        } else if self.report.failed() > 0 {
+            1
+        } else {
+            0
+        })
+    }
+}
+
+// xxHash64 of a new file, None (and a message) if it cannot be read.
+fn digest(path: &Path) -> Option<String> {
+    match hash::file_digest(path) {
+        Ok(digest) => Some(digest),
+        Err(err) => {
+            say!("Hashing {} failed: {}", path.display(), err);
+            None
+        }
+    }
+}
+
+// A video of a playlist, downloaded with the playlist's directives except its `items=`.
+fn child_entry(playlist: &UrlEntry, url: String) -> UrlEntry {
+    UrlEntry {
+        url,
+        directives: Directives {
+            items: None,
+            ..playlist.directives.clone()
+        },
+        file: playlist.file.clone(),
+        line: playlist.line,
+        parent: Some(
+            playlist
+                .parent
+                .clone()
+                .unwrap_or_else(|| playlist.url.clone()),
+        ),
+    }
+}
+
+// Compares known values, with unknown ones after all of them.
+fn missing_last<T: Ord>(a: Option<T>, b: Option<T>) -> cmp::Ordering {
+    match (a, b) {
+        (Some(a), Some(b)) => a.cmp(&b),
+        (Some(_), None) => cmp::Ordering::Less,
+        (None, Some(_)) => cmp::Ordering::Greater,
+        (None, None) => cmp::Ordering::Equal,
+    }
+}
+
+// Random order, so that a run spreads its requests over the sites.
+fn shuffle(entries: &mut [UrlEntry]) {
+    // RandomState is seeded from the OS
+    let state = RandomState::new();
+    entries.sort_by_cached_key(|entry| state.hash_one(&entry.url));
+}
+
+// `STATUS URL SOURCE FILES ERROR MESSAGE`, tab-separated, with `-` for empty fields.
+fn porcelain_line(entry: &UrlEntry, status: &Status) -> String {
+    let (files, error, message) = match status {
+        Status::Downloaded(files) => (*files, "-", "-".to_string()),
+        Status::Skipped | Status::Pending => (0, "-", "-".to_string()),
+        Status::Failed(class, message) => (0, class.label(), message.replace(['\t', '\n'], " ")),
+    };
+    format!(
+        "{}\t{}\t{}:{}\t{}\t{}\t{}",
+        status.name(),
+        entry.url,
+        entry.file.display(),
+        entry.line,
+        files,
+        error,
+        message
+    )
+}
+
+// `default.urls` downloads into the output directory itself, any other file into a
+// subdirectory named after it.
+fn output_dir_for(output: &Path, urls_file: &Path) -> PathBuf {
+    let file_stem = urls_file.file_stem().unwrap().to_str().unwrap();
+    if file_stem == "default" {
+        output.to_path_buf()
+    } else if cfg!(target_os = "windows") {
+        output.join(paths::windows_safe(file_stem))
+    } else {
+        output.join(file_stem)
+    }
+}
+
+// The entry to retry `pending` with, None once its line is gone from the .urls file. A video of
+// a playlist is retried as long as the playlist is listed.
+fn find_entry(pending: &Pending) -> io::Result<Option<UrlEntry>> {
+    if !pending.file.is_file() {
+        return Ok(None);
+    }
+    let listed = pending.parent.as_ref().unwrap_or(&pending.url);
+    Ok(urls::read_entries(&pending.file)?
+        .into_iter()
+        .find(|entry| &entry.url == listed)
+        .map(|entry| match &pending.parent {
+            Some(_) => child_entry(&entry, pending.url.clone()),
+            None => entry,
+        }))
+}
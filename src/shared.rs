@@ -0,0 +1,251 @@
+use std::collections::HashSet;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::process::{self, Command, Stdio};
+
+use crate::{archive, date};
+
+// How long a machine may hold a video it started downloading; a crashed run's claims expire.
+const CLAIM_SECONDS: u64 = 6 * 3600;
+const REDIS_ARCHIVE_KEY: &str = "dlyt:archive";
+const REDIS_CLAIM_PREFIX: &str = "dlyt:claim:";
+
+// A download archive several machines download against, from `--shared-archive`. Videos are
+// claimed before they are downloaded, so two machines never fetch the same one at once.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SharedArchive {
+    // A file on a network share, in yt-dlp's format, with the claims in `<file>.claims`. Both
+    // are only changed while holding a lock on `<file>.lock`.
+    File(PathBuf),
+    // A Redis set, with the claims as expiring keys, reached through `redis-cli`
+    Redis(String),
+}
+
+impl SharedArchive {
+    pub fn parse(location: &str) -> SharedArchive {
+        if location.starts_with("redis://") || location.starts_with("rediss://") {
+            SharedArchive::Redis(location.to_string())
+        } else {
+            SharedArchive::File(PathBuf::from(location))
+        }
+    }
+
+    // Every archive key in it.
+    pub fn load(&self) -> io::Result<HashSet<String>> {
+        match self {
+            SharedArchive::File(path) => {
+                let _lock = lock(path, false)?;
+                archive::load(path)
+            }
+            SharedArchive::Redis(url) => Ok(redis(url, &["SMEMBERS", REDIS_ARCHIVE_KEY])?
+                .lines()
+                .map(str::trim)
+                .filter(|line| !line.is_empty())
+                .map(str::to_string)
+                .collect()),
+        }
+    }
+
+    // Brings the local archive and the shared one up to date with each other, adding the
+    // shared keys to `known` as well.
+    pub fn merge(&self, archive_file: &Path, known: &mut HashSet<String>) -> io::Result<()> {
+        let shared = self.load()?;
+        let missing_here: Vec<&String> = shared.difference(known).collect();
+        if !missing_here.is_empty() {
+            let mut file = OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(archive_file)?;
+            for id in &missing_here {
+                writeln!(file, "{}", id)?;
+            }
+        }
+        let missing_there: Vec<String> = known.difference(&shared).cloned().collect();
+        self.add(&missing_there)?;
+        known.extend(shared);
+        Ok(())
+    }
+
+    // Reserves a video for this machine. False when it is already archived or another machine
+    // is downloading it.
+    pub fn claim(&self, id: &str) -> io::Result<bool> {
+        let me = machine();
+        match self {
+            SharedArchive::File(path) => {
+                let _lock = lock(path, true)?;
+                if archive::load(path)?.contains(id) {
+                    return Ok(false);
+                }
+                let now = date::now();
+                let mut claims = load_claims(path, now)?;
+                match claims.iter().find(|(claimed, _, _)| claimed == id) {
+                    Some((_, owner, _)) => return Ok(*owner == me),
+                    None => claims.push((id.to_string(), me, now + CLAIM_SECONDS)),
+                }
+                save_claims(path, &claims)?;
+                Ok(true)
+            }
+            SharedArchive::Redis(url) => {
+                if redis(url, &["SISMEMBER", REDIS_ARCHIVE_KEY, id])?.trim() == "1" {
+                    return Ok(false);
+                }
+                let key = format!("{}{}", REDIS_CLAIM_PREFIX, id);
+                let ttl = CLAIM_SECONDS.to_string();
+                if redis(url, &["SET", &key, &me, "NX", "EX", &ttl])?.trim() == "OK" {
+                    return Ok(true);
+                }
+                Ok(redis(url, &["GET", &key])?.trim() == me)
+            }
+        }
+    }
+
+    // Records finished videos and lets go of their claims.
+    pub fn add(&self, ids: &[String]) -> io::Result<()> {
+        if ids.is_empty() {
+            return Ok(());
+        }
+        match self {
+            SharedArchive::File(path) => {
+                let _lock = lock(path, true)?;
+                let known = archive::load(path)?;
+                let mut file = OpenOptions::new().create(true).append(true).open(path)?;
+                for id in ids.iter().filter(|id| !known.contains(*id)) {
+                    writeln!(file, "{}", id)?;
+                }
+                file.sync_all()?;
+                let claims: Vec<_> = load_claims(path, date::now())?
+                    .into_iter()
+                    .filter(|(claimed, _, _)| !ids.contains(claimed))
+                    .collect();
+                save_claims(path, &claims)
+            }
+            SharedArchive::Redis(url) => {
+                let mut args = vec!["SADD", REDIS_ARCHIVE_KEY];
+                args.extend(ids.iter().map(String::as_str));
+                redis(url, &args)?;
+                let keys: Vec<String> = ids
+                    .iter()
+                    .map(|id| format!("{}{}", REDIS_CLAIM_PREFIX, id))
+                    .collect();
+                let mut args = vec!["DEL"];
+                args.extend(keys.iter().map(String::as_str));
+                redis(url, &args).map(drop)
+            }
+        }
+    }
+
+    // Lets go of a video this machine claimed but did not download, for another to try.
+    pub fn release(&self, id: &str) -> io::Result<()> {
+        let me = machine();
+        match self {
+            SharedArchive::File(path) => {
+                let _lock = lock(path, true)?;
+                let claims: Vec<_> = load_claims(path, date::now())?
+                    .into_iter()
+                    .filter(|(claimed, owner, _)| !(claimed == id && *owner == me))
+                    .collect();
+                save_claims(path, &claims)
+            }
+            SharedArchive::Redis(url) => {
+                let key = format!("{}{}", REDIS_CLAIM_PREFIX, id);
+                if redis(url, &["GET", &key])?.trim() == me {
+                    redis(url, &["DEL", &key])?;
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+// Who holds a claim: the host name and process, so two runs on one machine don't share them.
+fn machine() -> String {
+    let host = ["HOSTNAME", "COMPUTERNAME"]
+        .iter()
+        .find_map(|name| std::env::var(name).ok())
+        .or_else(|| fs::read_to_string("/etc/hostname").ok())
+        .map(|host| host.trim().to_string())
+        .filter(|host| !host.is_empty())
+        .unwrap_or_else(|| "localhost".to_string());
+    format!("{}:{}", host, process::id())
+}
+
+// Holds `<file>.lock` until dropped; shared for reading, exclusive for changes. Over NFS the
+// lock is taken on the server.
+fn lock(path: &Path, exclusive: bool) -> io::Result<File> {
+    let file = OpenOptions::new()
+        .create(true)
+        .truncate(false)
+        .write(true)
+        .open(with_suffix(path, "lock"))?;
+    if exclusive {
+        file.lock()?;
+    } else {
+        file.lock_shared()?;
+    }
+    Ok(file)
+}
+
+fn with_suffix(path: &Path, suffix: &str) -> PathBuf {
+    let mut name = path.as_os_str().to_owned();
+    name.push(".");
+    name.push(suffix);
+    PathBuf::from(name)
+}
+
+// The claims that have not expired, as (archive key, machine, expiry).
+fn load_claims(path: &Path, now: u64) -> io::Result<Vec<(String, String, u64)>> {
+    let contents = match fs::read_to_string(with_suffix(path, "claims")) {
+        Ok(contents) => contents,
+        Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(err) => return Err(err),
+    };
+    Ok(contents
+        .lines()
+        .filter_map(|line| {
+            let mut fields = line.split('\t');
+            let id = fields.next()?.to_string();
+            let owner = fields.next()?.to_string();
+            let expires = fields.next()?.parse().ok()?;
+            Some((id, owner, expires))
+        })
+        .filter(|(_, _, expires)| *expires > now)
+        .collect())
+}
+
+// Written next to the claims file first, so a reader never sees half of it.
+fn save_claims(path: &Path, claims: &[(String, String, u64)]) -> io::Result<()> {
+    let target = with_suffix(path, "claims");
+    let temp = with_suffix(path, "claims.tmp");
+    let mut contents = String::new();
+    for (id, owner, expires) in claims {
+        contents.push_str(&format!("{}\t{}\t{}\n", id, owner, expires));
+    }
+    fs::write(&temp, contents)?;
+    fs::rename(&temp, target)
+}
+
+fn redis(url: &str, args: &[&str]) -> io::Result<String> {
+    let output = Command::new("redis-cli")
+        .arg("-u")
+        .arg(url)
+        .arg("--raw")
+        .args(args)
+        .stdin(Stdio::null())
+        .output()?;
+    let stdout = String::from_utf8_lossy(&output.stdout).to_string();
+    // redis-cli exits with 0 on most errors and prints them instead
+    if !output.status.success() || stdout.starts_with("ERR") || stdout.starts_with("WRONGTYPE") {
+        let message = String::from_utf8_lossy(&output.stderr).trim().to_string();
+        return Err(io::Error::other(format!(
+            "redis-cli {} failed: {}",
+            args[0],
+            if message.is_empty() {
+                stdout.trim()
+            } else {
+                &message
+            }
+        )));
+    }
+    Ok(stdout)
+}
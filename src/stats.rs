@@ -0,0 +1,133 @@
+use std::collections::BTreeMap;
+use std::io;
+use std::path::Path;
+
+use crate::failure::ErrorClass;
+use crate::history::{self, Entry};
+use crate::units::human_size;
+use crate::{date, urls};
+
+const DAYS: u64 = 14;
+const WEEKS: u64 = 8;
+
+// Files, bytes and download time of a group of history entries.
+#[derive(Default)]
+struct Totals {
+    files: usize,
+    bytes: u64,
+    seconds: f64,
+}
+
+impl Totals {
+    fn add(&mut self, entry: &Entry) {
+        self.files += 1;
+        self.bytes += entry.size;
+        self.seconds += entry.seconds;
+    }
+
+    fn speed(&self) -> String {
+        if self.seconds > 0.0 {
+            format!(
+                "{}/s",
+                human_size((self.bytes as f64 / self.seconds) as u64)
+            )
+        } else {
+            "-".to_string()
+        }
+    }
+}
+
+pub fn show(history_file: &Path, attempts_file: &Path, limit: usize) -> io::Result<()> {
+    let entries = history::load(history_file)?;
+    let attempts = history::load_attempts(attempts_file)?;
+    if entries.is_empty() && attempts.is_empty() {
+        println!("No downloads recorded yet in {}.", history_file.display());
+        return Ok(());
+    }
+
+    let now = date::now();
+    let mut days: BTreeMap<u64, Totals> = BTreeMap::new();
+    let mut weeks: BTreeMap<u64, Totals> = BTreeMap::new();
+    let mut uploaders: BTreeMap<&str, Totals> = BTreeMap::new();
+    for entry in &entries {
+        if let Some(secs) = date::parse_timestamp(&entry.timestamp) {
+            if secs + DAYS * 86_400 > now {
+                days.entry(secs - secs % 86_400).or_default().add(entry);
+            }
+            if secs + WEEKS * 7 * 86_400 > now {
+                weeks.entry(date::week_start(secs)).or_default().add(entry);
+            }
+        }
+        let uploader = if entry.uploader.is_empty() || entry.uploader == "NA" {
+            "(unknown)"
+        } else {
+            &entry.uploader
+        };
+        uploaders.entry(uploader).or_default().add(entry);
+    }
+
+    println!("Downloads per day (last {} days):", DAYS);
+    for (day, totals) in &days {
+        println!(
+            "  {}  {:>5} files  {:>10}",
+            date::format_date(*day),
+            totals.files,
+            human_size(totals.bytes)
+        );
+    }
+
+    println!("Downloads per week (last {} weeks):", WEEKS);
+    for (week, totals) in &weeks {
+        println!(
+            "  week of {}  {:>5} files  {:>10}  {:>12}",
+            date::format_date(*week),
+            totals.files,
+            human_size(totals.bytes),
+            totals.speed()
+        );
+    }
+
+    let mut uploaders: Vec<(&str, Totals)> = uploaders.into_iter().collect();
+    uploaders.sort_by_key(|(_, totals)| std::cmp::Reverse(totals.bytes));
+    println!("Largest uploaders:");
+    for (uploader, totals) in uploaders.iter().take(limit) {
+        println!(
+            "  {:>10}  {:>5} files  {}",
+            human_size(totals.bytes),
+            totals.files,
+            uploader
+        );
+    }
+
+    // Attempts, failures and failures per class for each domain
+    let mut domains: BTreeMap<&str, (usize, usize, BTreeMap<ErrorClass, usize>)> = BTreeMap::new();
+    let recent = attempts.iter().filter(|attempt| {
+        date::parse_timestamp(&attempt.timestamp)
+            .is_some_and(|secs| secs + WEEKS * 7 * 86_400 > now)
+    });
+    for attempt in recent {
+        let counts = domains.entry(urls::domain(&attempt.url)).or_default();
+        counts.0 += 1;
+        if let Some(class) = attempt.failure {
+            counts.1 += 1;
+            *counts.2.entry(class).or_default() += 1;
+        }
+    }
+    println!("Failure rate per domain (last {} weeks):", WEEKS);
+    for (domain, (total, failed, classes)) in &domains {
+        let common = classes
+            .iter()
+            .max_by_key(|(_, count)| **count)
+            .map(|(class, _)| format!(", mostly {}", class.label()))
+            .unwrap_or_default();
+        println!(
+            "  {:<24} {:>5} attempts  {:>5} failed ({:.1}%{})",
+            domain,
+            total,
+            failed,
+            *failed as f64 * 100.0 / *total as f64,
+            common
+        );
+    }
+    Ok(())
+}
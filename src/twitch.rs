@@ -0,0 +1,96 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::download::Quality;
+use crate::json;
+use crate::urls;
+
+// Connections fetching the segments of a VOD at once; yt-dlp gets one at a time otherwise.
+const VOD_FRAGMENTS: u32 = 4;
+
+// twitch.tv, m.twitch.tv, clips.twitch.tv, ...
+pub fn is_twitch(url: &str) -> bool {
+    let domain = urls::domain(url);
+    domain == "twitch.tv" || domain.ends_with(".twitch.tv")
+}
+
+// A past broadcast, `twitch.tv/videos/<id>`, as opposed to a channel or a clip.
+pub fn is_vod(url: &str) -> bool {
+    is_twitch(url)
+        && url
+            .split(['?', '#'])
+            .next()
+            .unwrap_or_default()
+            .split('/')
+            .skip_while(|part| *part != "videos")
+            .nth(1)
+            .is_some_and(|id| !id.is_empty() && id.bytes().all(|b| b.is_ascii_digit()))
+}
+
+// Twitch streams come with their audio, in renditions such as 1080p60 and 720p30, plus an
+// `audio_only` one; there is nothing to merge. A height limit below the lowest rendition takes
+// the lowest one.
+pub fn format(quality: Quality) -> String {
+    match quality {
+        Quality::Best => "best".to_string(),
+        Quality::Height(height) => format!("best[height<={}]/worst[vcodec!=none]", height),
+        Quality::AudioOnly => "bestaudio/worst".to_string(),
+    }
+}
+
+// yt-dlp arguments for a VOD: with `fragments` its HLS segments fetched several at a time, and
+// with `chapters` (--twitch-chapters) the game segments printed next to `output` as JSON for
+// `write_chapters`.
+pub fn vod_args(output: &Path, chapters: bool, fragments: bool) -> Vec<String> {
+    let mut args = Vec::new();
+    if fragments {
+        args.push("--concurrent-fragments".to_string());
+        args.push(VOD_FRAGMENTS.to_string());
+    }
+    if chapters {
+        let output = output.to_string_lossy();
+        let stem = output.strip_suffix(".%(ext)s").unwrap_or(&output);
+        args.push("--print-to-file".to_string());
+        args.push("after_move:%(chapters)j".to_string());
+        args.push(format!("{}.chapters.json", stem));
+    }
+    args
+}
+
+// Turns the chapters yt-dlp printed for `file` into `Title.chapters.txt`, one `0:00:00 Game`
+// line per segment. VODs without chapters get no file.
+pub fn write_chapters(file: &Path) {
+    let printed = sibling(file, "chapters.json");
+    let contents = fs::read_to_string(&printed).unwrap_or_default();
+    let _ = fs::remove_file(&printed);
+    // yt-dlp appends, so a VOD downloaded again has several lines; the last one is current
+    let Some(Ok(chapters)) = contents.lines().last().map(json::parse) else {
+        return;
+    };
+    let lines: Vec<String> = chapters
+        .as_array()
+        .iter()
+        .filter_map(|chapter| {
+            let start = chapter.get("start_time")?.as_f64()?.max(0.0) as u64;
+            let title = chapter.get("title").and_then(|t| t.as_str()).unwrap_or("");
+            Some(format!(
+                "{}:{:02}:{:02} {}\n",
+                start / 3600,
+                start / 60 % 60,
+                start % 60,
+                title
+            ))
+        })
+        .collect();
+    if !lines.is_empty() {
+        let _ = fs::write(sibling(file, "chapters.txt"), lines.concat());
+    }
+}
+
+// `Title.<suffix>` next to `Title.mp4`.
+fn sibling(file: &Path, suffix: &str) -> PathBuf {
+    let mut name = file.file_stem().unwrap_or_default().to_owned();
+    name.push(".");
+    name.push(suffix);
+    file.with_file_name(name)
+}
@@ -0,0 +1,214 @@
+use std::fs::{self, OpenOptions};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+
+use crate::date;
+use crate::download::Metadata;
+use crate::layout::Layout;
+
+// A URL line from a .urls file, remembering where it came from. Options for a single URL
+// follow it on the same line, separated by `|`: `https://… | items=1-25`. A line starting with
+// `|` sets directives for all URLs below it: `| format=bestaudio`.
+#[derive(Debug, Clone)]
+pub struct UrlEntry {
+    pub url: String,
+    pub directives: Directives,
+    pub file: PathBuf,
+    pub line: usize,
+    // The playlist or channel URL on `line` for one of its videos, None for the line's own URL
+    pub parent: Option<String>,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct Directives {
+    // Value for yt-dlp's --playlist-items
+    pub items: Option<String>,
+    // yt-dlp format selector used verbatim
+    pub format: Option<String>,
+    // Overrides --metadata, e.g. `| metadata=embed` to keep a folder free of sidecars
+    pub metadata: Option<Metadata>,
+    // Overrides --layout, e.g. `| layout=music` for a list of albums
+    pub layout: Option<Layout>,
+}
+
+pub fn read_entries(path: &Path) -> io::Result<Vec<UrlEntry>> {
+    let contents = fs::read_to_string(path)?;
+    let mut entries = Vec::new();
+    // Directives from `| key=value` lines, applied before each URL's own
+    let mut file_directives: Vec<&str> = Vec::new();
+
+    for (index, line) in contents.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+
+        if let Some(rest) = line.strip_prefix('|') {
+            for part in rest
+                .split('|')
+                .map(str::trim)
+                .filter(|part| !part.is_empty())
+            {
+                match Directives::default().set(part, "") {
+                    Ok(()) => file_directives.push(part),
+                    Err(err) => say!(
+                        "Ignoring directive `{}` in {}:{}: {}",
+                        part,
+                        path.display(),
+                        index + 1,
+                        err
+                    ),
+                }
+            }
+            continue;
+        }
+
+        let mut parts = line.split('|').map(str::trim);
+        let url = parts.next().unwrap_or_default().to_string();
+        let mut directives = Directives::default();
+        for part in &file_directives {
+            let _ = directives.set(part, &url);
+        }
+        for part in parts.filter(|part| !part.is_empty()) {
+            if let Err(err) = directives.set(part, &url) {
+                say!(
+                    "Ignoring directive `{}` in {}:{}: {}",
+                    part,
+                    path.display(),
+                    index + 1,
+                    err
+                );
+            }
+        }
+
+        entries.push(UrlEntry {
+            url,
+            directives,
+            file: path.to_path_buf(),
+            line: index + 1,
+            parent: None,
+        });
+    }
+
+    Ok(entries)
+}
+
+impl Directives {
+    fn set(&mut self, directive: &str, url: &str) -> Result<(), String> {
+        let (key, value) = directive.split_once('=').unwrap_or((directive, ""));
+        match key.trim() {
+            "items" => self.items = Some(playlist_items(value.trim(), url)?),
+            "format" if !value.trim().is_empty() => self.format = Some(value.trim().to_string()),
+            "metadata" => {
+                self.metadata =
+                    Some(Metadata::from_name(value.trim()).ok_or_else(|| {
+                        format!("`{}` is not sidecar, embed, both or none", value)
+                    })?)
+            }
+            "layout" => {
+                self.layout = Some(
+                    Layout::from_name(value.trim())
+                        .ok_or_else(|| format!("`{}` is not default, music or shorts", value))?,
+                )
+            }
+            other => return Err(format!("unknown directive `{}`", other)),
+        }
+        Ok(())
+    }
+}
+
+// `items=1-25,30` is passed through to yt-dlp; `items=latest:N` selects the N newest entries,
+// which are listed first on channel pages and last in ordinary playlists.
+fn playlist_items(value: &str, url: &str) -> Result<String, String> {
+    if let Some(count) = value.strip_prefix("latest:") {
+        let count: u32 = count
+            .parse()
+            .map_err(|_| format!("`{}` is not a number of items", count))?;
+        return Ok(if is_channel(url) {
+            format!("1:{}", count)
+        } else {
+            format!("-{}:", count)
+        });
+    }
+
+    let valid = !value.is_empty()
+        && value
+            .chars()
+            .all(|c| c.is_ascii_digit() || matches!(c, '-' | ':' | ','));
+    if valid {
+        Ok(value.to_string())
+    } else {
+        Err(format!("`{}` is not a playlist item range", value))
+    }
+}
+
+fn is_channel(url: &str) -> bool {
+    ["/@", "/channel/", "/c/", "/user/"]
+        .iter()
+        .any(|marker| url.contains(marker))
+}
+
+// Comments out the line of `entry` in its .urls file, noting the date and reason. Leaves the
+// file alone if the line changed since it was read, and for a video of a playlist, whose line
+// is the playlist's.
+pub fn comment_out(entry: &UrlEntry, reason: &str) -> io::Result<bool> {
+    if entry.parent.is_some() || !entry.file.is_file() {
+        return Ok(false);
+    }
+    let contents = fs::read_to_string(&entry.file)?;
+    let mut lines: Vec<String> = contents.lines().map(str::to_string).collect();
+
+    // Line 0: the URL was queued directly, see `dlyt listen`
+    let Some(line) = entry.line.checked_sub(1).and_then(|i| lines.get_mut(i)) else {
+        return Ok(false);
+    };
+    if line.split('|').next().map(str::trim) != Some(entry.url.as_str()) {
+        return Ok(false);
+    }
+    *line = format!(
+        "# [dlyt {}: {}] {}",
+        &date::format_timestamp(date::now())[..10],
+        reason.replace(['\n', ']'], " "),
+        line
+    );
+
+    let mut updated = lines.join("\n");
+    if contents.ends_with('\n') {
+        updated.push('\n');
+    }
+    fs::write(&entry.file, updated)?;
+    Ok(true)
+}
+
+// Appends a URL to a .urls file, creating it if needed. Returns false if the URL is already
+// listed.
+pub fn append(path: &Path, url: &str) -> io::Result<bool> {
+    let contents = match fs::read_to_string(path) {
+        Ok(contents) => contents,
+        Err(err) if err.kind() == io::ErrorKind::NotFound => String::new(),
+        Err(err) => return Err(err),
+    };
+    let listed = contents
+        .lines()
+        .filter(|line| !line.trim_start().starts_with('#'))
+        .any(|line| line.split('|').next().map(str::trim) == Some(url));
+    if listed {
+        return Ok(false);
+    }
+
+    let mut file = OpenOptions::new().create(true).append(true).open(path)?;
+    if !contents.is_empty() && !contents.ends_with('\n') {
+        writeln!(file)?;
+    }
+    writeln!(file, "{}", url)?;
+    Ok(true)
+}
+
+// Host of a URL without `www.`, e.g. `youtube.com`. Empty if there is none.
+pub fn domain(url: &str) -> &str {
+    let rest = url.split_once("://").map_or(url, |(_, rest)| rest);
+    let host = rest.split(['/', '?', '#']).next().unwrap_or_default();
+    let host = host.rsplit_once('@').map_or(host, |(_, host)| host);
+    let host = host.split(':').next().unwrap_or_default();
+    host.strip_prefix("www.").unwrap_or(host)
+}
@@ -0,0 +1,237 @@
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use std::sync::OnceLock;
+
+use crate::{container, hash, paths};
+
+// Checksums yt-dlp publishes next to every release
+const CHECKSUMS: &str = "SHA2-256SUMS";
+
+// The yt-dlp dlyt installs with `dlyt update`, used instead of the one on the PATH once there.
+static MANAGED: OnceLock<PathBuf> = OnceLock::new();
+
+// Where `dlyt update` gets yt-dlp from, from `--update-channel`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Channel {
+    Stable,
+    // Built from every day's changes, for extractors fixed after the last release
+    Nightly,
+}
+
+impl Channel {
+    pub fn from_name(name: &str) -> Option<Channel> {
+        match name {
+            "stable" => Some(Channel::Stable),
+            "nightly" => Some(Channel::Nightly),
+            _ => None,
+        }
+    }
+
+    fn release_url(self) -> &'static str {
+        match self {
+            Channel::Stable => "https://github.com/yt-dlp/yt-dlp/releases/latest/download/",
+            Channel::Nightly => {
+                "https://github.com/yt-dlp/yt-dlp-nightly-builds/releases/latest/download/"
+            }
+        }
+    }
+}
+
+// Sets up the managed yt-dlp in `<dir>/bin`, with `dir` the data directory so every root uses
+// the same one.
+pub fn init(dir: &Path) {
+    let _ = MANAGED.set(dir.join(paths::BIN_DIR).join(binary_name()));
+}
+
+fn binary_name() -> &'static str {
+    if cfg!(windows) {
+        "yt-dlp.exe"
+    } else {
+        "yt-dlp"
+    }
+}
+
+// The yt-dlp on the PATH, looked up once; on Windows it may be a `.cmd` shim.
+static ON_PATH: OnceLock<Option<PathBuf>> = OnceLock::new();
+
+// The one in the container with --in-container, else the managed yt-dlp when there is one,
+// else the one on the PATH.
+pub fn command() -> Command {
+    if container::is_active() {
+        return container::command("yt-dlp");
+    }
+    match MANAGED.get().filter(|managed| managed.is_file()) {
+        Some(managed) => Command::new(managed),
+        None => match ON_PATH.get_or_init(|| paths::find_program("yt-dlp")) {
+            Some(program) => Command::new(program),
+            None => Command::new("yt-dlp"),
+        },
+    }
+}
+
+pub fn is_managed() -> bool {
+    MANAGED.get().is_some_and(|managed| managed.is_file())
+}
+
+// `yt-dlp --version`, e.g. `2024.08.06`, or None when it does not run.
+pub fn version() -> Option<String> {
+    let output = command()
+        .arg("--version")
+        .stdin(Stdio::null())
+        .output()
+        .ok()?;
+    let version = String::from_utf8_lossy(&output.stdout).trim().to_string();
+    (output.status.success() && !version.is_empty()).then_some(version)
+}
+
+// The release file for this platform: a standalone build where there is one, else the zipapp
+// that needs Python.
+fn artifact() -> &'static str {
+    match (std::env::consts::OS, std::env::consts::ARCH) {
+        ("linux", "x86_64") => "yt-dlp_linux",
+        ("linux", "aarch64") => "yt-dlp_linux_aarch64",
+        ("linux", "arm") => "yt-dlp_linux_armv7l",
+        ("macos", _) => "yt-dlp_macos",
+        ("windows", "x86") => "yt-dlp_x86.exe",
+        ("windows", _) => "yt-dlp.exe",
+        _ => "yt-dlp",
+    }
+}
+
+// Installs the latest yt-dlp of `channel` as the managed one: downloaded over HTTPS, checked
+// against the published SHA-256, and kept only if it runs. The one it replaces stays as
+// `yt-dlp.previous` for `--rollback`. Returns false when the update failed.
+pub fn update(channel: Channel) -> io::Result<bool> {
+    let Some(managed) = MANAGED.get() else {
+        return Ok(false);
+    };
+    let dir = managed.parent().unwrap_or(Path::new("."));
+    fs::create_dir_all(dir)?;
+    let artifact = artifact();
+    let download = dir.join(format!("{}.download", binary_name()));
+
+    println!(
+        "Downloading {} from {}",
+        artifact,
+        channel.release_url().trim_end_matches('/')
+    );
+    let sums = match fetch(&format!("{}{}", channel.release_url(), CHECKSUMS), None) {
+        Ok(sums) => sums,
+        Err(err) => {
+            println!("Could not get the checksums: {}", err);
+            return Ok(false);
+        }
+    };
+    let Some(expected) = sums.lines().find_map(|line| {
+        let (sum, name) = line.split_once(char::is_whitespace)?;
+        (name.trim().trim_start_matches('*') == artifact).then(|| sum.to_lowercase())
+    }) else {
+        println!("{} does not list {}.", CHECKSUMS, artifact);
+        return Ok(false);
+    };
+    if let Err(err) = fetch(
+        &format!("{}{}", channel.release_url(), artifact),
+        Some(&download),
+    ) {
+        println!("Could not download {}: {}", artifact, err);
+        let _ = fs::remove_file(&download);
+        return Ok(false);
+    }
+    let actual = hash::file_sha256(&download)?;
+    if actual != expected {
+        println!(
+            "The download does not match the published checksum ({} instead of {}); not installed.",
+            actual, expected
+        );
+        fs::remove_file(&download)?;
+        return Ok(false);
+    }
+    if managed.is_file() && hash::file_sha256(managed)? == actual {
+        fs::remove_file(&download)?;
+        println!(
+            "yt-dlp {} is up to date.",
+            version().unwrap_or_else(|| "(unknown version)".to_string())
+        );
+        return Ok(true);
+    }
+    make_executable(&download)?;
+
+    let previous = previous(managed);
+    if managed.is_file() {
+        fs::rename(managed, &previous)?;
+    }
+    fs::rename(&download, managed)?;
+    match version() {
+        Some(version) => {
+            println!("Installed yt-dlp {} in {}.", version, managed.display());
+            Ok(true)
+        }
+        None => {
+            println!("The new yt-dlp does not run; keeping the one before it.");
+            fs::remove_file(managed)?;
+            if previous.is_file() {
+                fs::rename(&previous, managed)?;
+            }
+            Ok(false)
+        }
+    }
+}
+
+// Swaps the managed yt-dlp with the one the last update replaced. Returns false when there is
+// none.
+pub fn rollback() -> io::Result<bool> {
+    let Some(managed) = MANAGED.get() else {
+        return Ok(false);
+    };
+    let previous = previous(managed);
+    if !previous.is_file() {
+        println!("There is no earlier yt-dlp to go back to.");
+        return Ok(false);
+    }
+    let swap = managed.with_extension("swap");
+    if managed.is_file() {
+        fs::rename(managed, &swap)?;
+    }
+    fs::rename(&previous, managed)?;
+    if swap.is_file() {
+        fs::rename(&swap, &previous)?;
+    }
+    println!(
+        "Went back to yt-dlp {}; `dlyt update --rollback` again undoes this.",
+        version().unwrap_or_else(|| "(unknown version)".to_string())
+    );
+    Ok(true)
+}
+
+fn previous(managed: &Path) -> PathBuf {
+    managed.with_file_name(format!("{}.previous", binary_name()))
+}
+
+// GETs `url` with curl, HTTPS only, into `file`, or returns the body when there is none.
+pub fn fetch(url: &str, file: Option<&Path>) -> io::Result<String> {
+    let mut command = Command::new("curl");
+    command.args(["-fsSL", "--proto", "=https", "--tlsv1.2"]);
+    if let Some(file) = file {
+        command.arg("-o").arg(file);
+    }
+    let output = command.arg(url).stdin(Stdio::null()).output()?;
+    if !output.status.success() {
+        return Err(io::Error::other(
+            String::from_utf8_lossy(&output.stderr).trim().to_string(),
+        ));
+    }
+    Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+}
+
+#[cfg(unix)]
+fn make_executable(path: &Path) -> io::Result<()> {
+    use std::os::unix::fs::PermissionsExt;
+    fs::set_permissions(path, fs::Permissions::from_mode(0o755))
+}
+
+#[cfg(not(unix))]
+fn make_executable(_path: &Path) -> io::Result<()> {
+    Ok(())
+}